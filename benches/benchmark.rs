@@ -1,24 +1,122 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use opencv::prelude::Mat;
-use opencv::core::Vector;
-
-fn benchmark(c: &mut Criterion) {
-    let mut vector = Vector::<i32>::with_capacity(1000);
-    for x in 0..1000 {
-        vector.push(rand::random());  
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use opencv::core::{self, Mat, Scalar, CV_8UC1, CV_8UC3};
+use the::args::{Charset, ColorDepth};
+use the::backend::{ConversionBackend, CpuBackend};
+use the::config::Settings;
+use the::opencl::OpenClBackend;
+use the::terminal;
+
+// Terminal sizes in cells, from a classic 80x24 up to a 4K screen with a small font.
+const SIZES: [(i32, i32); 4] = [(80, 24), (200, 60), (320, 90), (480, 135)];
+
+// One and three bytes per character.
+const CHARSETS: [Charset; 2] = [Charset::Chars3, Charset::Gradient];
+
+fn random_frame(width: i32, height: i32, grayscale: bool) -> Mat {
+    let typ = if grayscale { CV_8UC1 } else { CV_8UC3 };
+    let mut frame = Mat::new_rows_cols_with_default(height, width, typ, Scalar::all(0.0)).unwrap();
+    core::randu(&mut frame, &Scalar::all(0.0), &Scalar::all(255.0)).unwrap();
+
+    frame
+}
+
+fn frame_name(width: i32, height: i32, grayscale: bool) -> String {
+    format!("{}x{}/{}", width, height, if grayscale { "grayscale" } else { "color" })
+}
+
+fn ascii_calculation(c: &mut Criterion, name: &str, backend: &mut dyn ConversionBackend, charset: Charset) {
+    let mut group = c.benchmark_group(format!("{}/{:?}", name, charset));
+    for (width, height) in SIZES {
+        for grayscale in [true, false] {
+            let frame = random_frame(width, height, grayscale);
+            group.throughput(Throughput::Elements((width * height) as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(frame_name(width, height, grayscale)), &frame, |b, frame| {
+                b.iter(|| backend.convert(frame).unwrap());
+            });
+        }
     }
-    let mat = unsafe { Mat::new_nd_vec(&vector, 1) };
+    group.finish();
+}
 
-        
+fn settings(charset: Charset) -> Settings {
+    Settings {
+        charset,
+        ..Settings::default()
+    }
 }
 
-fn cpu_ascii_calculation(frame: &Mat, chars: &String, step: u32, grayscale: bool) {
-    
+fn cpu_ascii_calculation(c: &mut Criterion) {
+    for charset in CHARSETS {
+        let mut backend = CpuBackend::new(&settings(charset), charset.chars()).unwrap();
+        ascii_calculation(c, "cpu", &mut backend, charset);
+    }
 }
 
-fn gpu_ascii_calculation() {
+fn gpu_ascii_calculation(c: &mut Criterion) {
+    for charset in CHARSETS {
+        let mut backend = match OpenClBackend::new(&settings(charset), charset.chars()) {
+            Ok(backend) => backend,
+            Err(error) => {
+                eprintln!("skipping OpenCL benchmarks: {}", error);
+                return;
+            },
+        };
+        ascii_calculation(c, "opencl", &mut backend, charset);
+    }
+}
 
+fn terminal_encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for charset in CHARSETS {
+        let mut backend = CpuBackend::new(&settings(charset), charset.chars()).unwrap();
+        for (width, height) in SIZES {
+            for grayscale in [true, false] {
+                let string = backend.convert(&random_frame(width, height, grayscale)).unwrap();
+                let mut out = Vec::with_capacity(string.string.len() * 8);
+                group.throughput(Throughput::Elements((width * height) as u64));
+                group.bench_with_input(BenchmarkId::new(format!("{:?}", charset), frame_name(width, height, grayscale)), &string, |b, string| {
+                    b.iter(|| {
+                        out.clear();
+                        terminal::encode(&mut out, string, (1, 1), ColorDepth::Truecolor).unwrap();
+                    });
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+fn terminal_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_update");
+    let mut backend = CpuBackend::new(&settings(Charset::Chars3), Charset::Chars3.chars()).unwrap();
+    for (width, height) in SIZES {
+        for grayscale in [true, false] {
+            let frame = random_frame(width, height, grayscale);
+            let previous = backend.convert(&frame).unwrap();
+            let mut string = backend.convert(&frame).unwrap();
+            let other = backend.convert(&random_frame(width, height, grayscale)).unwrap();
+
+            // A tenth of the rows changes, like a subtitle or something moving.
+            let rows = (height / 10) as usize;
+            let glyphs = rows * string.width as usize * string.char_len as usize;
+            string.string[..glyphs].copy_from_slice(&other.string[..glyphs]);
+            if !string.rgb.is_empty() {
+                let colors = rows * string.width as usize * 3;
+                string.rgb[..colors].copy_from_slice(&other.rgb[..colors]);
+            }
+
+            let mut out = Vec::with_capacity(string.string.len() * 8);
+            group.throughput(Throughput::Elements((width * height) as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(frame_name(width, height, grayscale)), &string, |b, string| {
+                b.iter(|| {
+                    out.clear();
+                    terminal::encode_update(&mut out, string, Some(&previous), (1, 1), ColorDepth::Truecolor).unwrap();
+                });
+            });
+        }
+    }
+    group.finish();
 }
 
-criterion_group!(benches, benchmark);
+criterion_group!(benches, cpu_ascii_calculation, gpu_ascii_calculation, terminal_encoding, terminal_update);
 criterion_main!(benches);
@@ -0,0 +1,131 @@
+use opencv::core::{self, MatTraitConst, MatTraitManual, Size, Vector};
+use opencv::imgproc::{self, CLAHETrait};
+use opencv::prelude::Mat;
+
+use crate::args::Equalize;
+use crate::config::Settings;
+
+// How much a key press changes the brightness, and the factor it changes contrast and gamma by.
+const BRIGHTNESS_STEP: i16 = 8;
+const FACTOR_STEP: f64 = 1.1;
+
+// CLAHE equalizes every tile of an 8x8 grid on its own, limiting how much contrast it adds.
+const CLAHE_CLIP_LIMIT: f64 = 2.0;
+const CLAHE_TILES: i32 = 8;
+
+/// A change made from the keyboard while playing, in key presses.
+#[derive(Debug, Clone, Copy)]
+pub enum Adjustment {
+    Brightness(i16),
+    Contrast(i32),
+    Gamma(i32),
+    Invert,
+    Equalize,
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Adjustments {
+    brightness: i16,
+    contrast: f64,
+    gamma: f64,
+    invert: bool,
+    equalize: Equalize,
+}
+
+/// Preprocesses resized frames before they are dithered and converted: histogram
+/// equalization of the luma first, then brightness, contrast, gamma and inversion
+/// through a lookup table.
+pub struct Adjuster {
+    current: Adjustments,
+    initial: Adjustments,
+    lut: [u8; 256],
+}
+
+impl Adjuster {
+    pub fn new(settings: &Settings) -> Self {
+        let initial = Adjustments {
+            brightness: settings.brightness,
+            contrast: settings.contrast,
+            gamma: settings.gamma,
+            invert: settings.invert,
+            equalize: settings.equalize,
+        };
+
+        Self {
+            current: initial,
+            initial,
+            lut: lut(&initial),
+        }
+    }
+
+    pub fn adjust(&mut self, adjustment: Adjustment) {
+        let current = &mut self.current;
+        match adjustment {
+            Adjustment::Brightness(steps) => current.brightness = current.brightness.saturating_add(steps.saturating_mul(BRIGHTNESS_STEP)).clamp(-255, 255),
+            Adjustment::Contrast(steps) => current.contrast *= FACTOR_STEP.powi(steps),
+            Adjustment::Gamma(steps) => current.gamma *= FACTOR_STEP.powi(steps),
+            Adjustment::Invert => current.invert = !current.invert,
+            Adjustment::Equalize => current.equalize = match current.equalize {
+                Equalize::None => Equalize::Global,
+                Equalize::Global => Equalize::Clahe,
+                Equalize::Clahe => Equalize::None,
+            },
+            Adjustment::Reset => *current = self.initial,
+        }
+
+        self.lut = lut(&self.current);
+    }
+
+    pub fn apply(&self, frame: &mut Mat) -> Result<(), String> {
+        if self.current.equalize != Equalize::None {
+            equalize(frame, self.current.equalize).map_err(|error| error.message)?;
+        }
+
+        if self.lut.iter().enumerate().any(|(value, &out)| value != out as usize) {
+            for value in frame.data_bytes_mut().map_err(|error| error.message)? {
+                *value = self.lut[*value as usize];
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn lut(adjustments: &Adjustments) -> [u8; 256] {
+    let mut lut = [0u8; 256];
+    for (value, out) in lut.iter_mut().enumerate() {
+        let value = (value as f64 - 128.0) * adjustments.contrast + 128.0 + adjustments.brightness as f64;
+        let value = 255.0 * (value.clamp(0.0, 255.0) / 255.0).powf(1.0 / adjustments.gamma);
+        *out = if adjustments.invert { 255.0 - value } else { value }.round() as u8;
+    }
+
+    lut
+}
+
+// Color frames are equalized on their luma only, so that hues stay the same.
+fn equalize(frame: &mut Mat, mode: Equalize) -> opencv::Result<()> {
+    if frame.channels() == 1 {
+        *frame = equalize_channel(frame, mode)?;
+        return Ok(());
+    }
+
+    let mut ycrcb = Mat::default();
+    imgproc::cvt_color(&*frame, &mut ycrcb, imgproc::COLOR_BGR2YCrCb, 0)?;
+    let mut channels = Vector::<Mat>::new();
+    core::split(&ycrcb, &mut channels)?;
+    channels.set(0, equalize_channel(&channels.get(0)?, mode)?)?;
+    core::merge(&channels, &mut ycrcb)?;
+    imgproc::cvt_color(&ycrcb, frame, imgproc::COLOR_YCrCb2BGR, 0)
+}
+
+fn equalize_channel(channel: &Mat, mode: Equalize) -> opencv::Result<Mat> {
+    let mut equalized = Mat::default();
+    match mode {
+        Equalize::None => channel.copy_to(&mut equalized)?,
+        Equalize::Global => imgproc::equalize_hist(channel, &mut equalized)?,
+        Equalize::Clahe => imgproc::create_clahe(CLAHE_CLIP_LIMIT, Size::new(CLAHE_TILES, CLAHE_TILES))?.apply(channel, &mut equalized)?,
+    }
+
+    Ok(equalized)
+}
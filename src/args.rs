@@ -1 +1,376 @@
-pub struct Args {}
+use std::path::{Path, PathBuf};
+
+use clap::builder::BoolishValueParser;
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+use crate::{ascii, glyph, iterm};
+
+/// Options given on the command line. Anything left out here is filled in from the
+/// environment, the selected profile and the config file, see `config::Settings`.
+#[derive(Parser, Debug, Clone)]
+#[command(version, about = "Play images and videos as text in the terminal")]
+#[command(after_help = "Options are taken from, in order of precedence: command-line flags, THE_* environment variables, \
+the selected [profile.<name>] table of the config file, the top level of the config file and built-in defaults.
+
+Keys while playing: space or k to pause, j and l to skip 10 seconds back and forward, b/B, c/C and g/G to lower/raise \
+brightness, contrast and gamma, i to invert, e to cycle histogram equalization, r to reset the adjustments and Ctrl-c to quit.")]
+pub struct Args {
+    /// Path or URI of the image or video to play
+    #[arg(env = "THE_INPUT", value_parser = parse_input)]
+    pub input: Option<String>,
+
+    /// Config file to read instead of $XDG_CONFIG_HOME/the/config.toml
+    #[arg(long, env = "THE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Profile from the config file to apply
+    #[arg(short, long, env = "THE_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Characters used to draw the picture, from darkest to brightest [default: chars3]
+    #[arg(short, long, env = "THE_CHARSET", value_enum)]
+    pub charset: Option<Charset>,
+
+    /// Custom characters to draw with instead of --charset, in any order; they are sorted by how much ink they have in --font
+    #[arg(long, env = "THE_CHARS")]
+    pub chars: Option<String>,
+
+    /// Resample the custom characters into a ramp of this many evenly spaced brightness levels; needs --chars
+    #[arg(long, env = "THE_RAMP_LEVELS", value_parser = clap::value_parser!(u16).range(2..))]
+    pub ramp_levels: Option<u16>,
+
+    /// Drop custom characters that have about the same amount of ink as a lighter one; needs --chars
+    #[arg(long, env = "THE_DEDUP", num_args = 0..=1, require_equals = true, default_missing_value = "true", value_parser = BoolishValueParser::new())]
+    pub dedup: Option<bool>,
+
+    /// How pixels are mapped to characters [default: ramp]
+    #[arg(short = 'r', long, env = "THE_MODE", value_enum)]
+    pub mode: Option<RenderMode>,
+
+    /// TrueType font characters are rasterized with for the shape mode and --chars [default: bundled DejaVu Sans Mono]
+    #[arg(long, env = "THE_FONT")]
+    pub font: Option<PathBuf>,
+
+    /// How braille dots are switched on [default: threshold]
+    #[arg(long, env = "THE_DOTS", value_enum)]
+    pub dots: Option<DotMode>,
+
+    /// Brightness from which a braille dot is switched on [default: 128]
+    #[arg(long, env = "THE_THRESHOLD")]
+    pub threshold: Option<u8>,
+
+    /// Sobel gradient strength, |gx| + |gy|, from which a pixel counts as an edge in the edge mode [default: 100]
+    #[arg(long, env = "THE_EDGE_THRESHOLD")]
+    pub edge_threshold: Option<u16>,
+
+    /// How brightness is spread over the levels of the ramp and colors over --color-levels and the --color-depth palette [default: none]
+    #[arg(long, env = "THE_DITHER", value_enum)]
+    pub dither: Option<DitherMode>,
+
+    /// Keep the error diffusion of parts of a video that barely change, so they do not shimmer
+    #[arg(long, env = "THE_STABLE_DITHER", num_args = 0..=1, require_equals = true, default_missing_value = "true", value_parser = BoolishValueParser::new())]
+    pub stable_dither: Option<bool>,
+
+    /// Reduce every color channel to this many evenly spaced levels
+    #[arg(long, env = "THE_COLOR_LEVELS", value_parser = clap::value_parser!(u16).range(2..=256))]
+    pub color_levels: Option<u16>,
+
+    /// Added to the brightness of every pixel, from -255 to 255 [default: 0]
+    #[arg(long, env = "THE_BRIGHTNESS", allow_negative_numbers = true, value_parser = clap::value_parser!(i16).range(-255..=255))]
+    pub brightness: Option<i16>,
+
+    /// Factor the distance of every pixel from middle gray is multiplied by [default: 1]
+    #[arg(long, env = "THE_CONTRAST", value_parser = parse_factor)]
+    pub contrast: Option<f64>,
+
+    /// Gamma correction, values above 1 brighten the shadows [default: 1]
+    #[arg(long, env = "THE_GAMMA", value_parser = parse_factor)]
+    pub gamma: Option<f64>,
+
+    /// Invert the picture, for terminals with a light background
+    #[arg(long, env = "THE_INVERT", num_args = 0..=1, require_equals = true, default_missing_value = "true", value_parser = BoolishValueParser::new())]
+    pub invert: Option<bool>,
+
+    /// Histogram equalization of every frame [default: none]
+    #[arg(long, env = "THE_EQUALIZE", value_enum)]
+    pub equalize: Option<Equalize>,
+
+    /// Where colors are drawn, or not at all [default: color]
+    #[arg(short = 'm', long = "color", env = "THE_COLOR", value_enum)]
+    pub color_mode: Option<ColorMode>,
+
+    /// Colors the terminal can show, auto detects them from COLORTERM, TERM and terminfo [default: auto]
+    #[arg(long, env = "THE_COLOR_DEPTH", value_enum)]
+    pub color_depth: Option<ColorDepth>,
+
+    /// How frames are drawn in the terminal [default: text]
+    #[arg(short, long, env = "THE_OUTPUT", value_enum)]
+    pub output: Option<Output>,
+
+    /// Where frames are converted to characters, auto uses OpenCL when a device is available [default: auto]
+    #[arg(short, long, env = "THE_BACKEND", value_enum)]
+    pub backend: Option<Backend>,
+
+    /// OpenCL device to use, by index or by part of its name, see --list-devices
+    #[arg(short, long, env = "THE_DEVICE")]
+    pub device: Option<String>,
+
+    /// Print the available OpenCL devices and exit
+    #[arg(long)]
+    pub list_devices: bool,
+
+    /// How the picture is scaled to the output size [default: fit]
+    #[arg(long, env = "THE_SCALE", value_enum)]
+    pub scale: Option<ScaleMode>,
+
+    /// Height of a terminal cell divided by its width, measured from the terminal when it reports its size in pixels and 2 otherwise
+    #[arg(long, env = "THE_CELL_ASPECT", value_parser = parse_factor)]
+    pub cell_aspect: Option<f64>,
+
+    /// Output width in columns, defaults to the terminal width
+    #[arg(short = 'W', long, env = "THE_WIDTH", value_parser = clap::value_parser!(u16).range(1..))]
+    pub width: Option<u16>,
+
+    /// Output height in rows, defaults to the terminal height
+    #[arg(short = 'H', long, env = "THE_HEIGHT", value_parser = clap::value_parser!(u16).range(1..))]
+    pub height: Option<u16>,
+
+    /// Playback frame rate, defaults to the frame rate of the video
+    #[arg(short, long, env = "THE_FPS", value_parser = parse_fps)]
+    pub fps: Option<f64>,
+
+    /// Position to start playing from, in seconds or as [[hh:]mm:]ss [default: 0]
+    #[arg(short, long, env = "THE_START", value_parser = parse_offset)]
+    pub start: Option<f64>,
+
+    /// Start over when the end of the video is reached
+    #[arg(short, long = "loop", env = "THE_LOOP", num_args = 0..=1, require_equals = true, default_missing_value = "true", value_parser = BoolishValueParser::new())]
+    pub looping: Option<bool>,
+
+    /// Do not play the audio track
+    #[arg(long, env = "THE_MUTE", num_args = 0..=1, require_equals = true, default_missing_value = "true", value_parser = BoolishValueParser::new())]
+    pub mute: Option<bool>,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Charset {
+    Chars1,
+    Chars2,
+    Chars3,
+    Gradient,
+    BlackWhite,
+    BwDotted,
+    Braille,
+}
+
+impl Charset {
+    pub fn chars(&self) -> &'static str {
+        match self {
+            Charset::Chars1 => ascii::CHARS1,
+            Charset::Chars2 => ascii::CHARS2,
+            Charset::Chars3 => ascii::CHARS3,
+            Charset::Gradient => ascii::GRADIENT,
+            Charset::BlackWhite => ascii::BLACKWHITE,
+            Charset::BwDotted => ascii::BW_DOTTED,
+            Charset::Braille => ascii::BRAILLE,
+        }
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenderMode {
+    /// One pixel per cell, drawn with the charset character of its brightness
+    Ramp,
+    /// Two pixels per cell stacked vertically, drawn as ▀ in two colors
+    HalfBlock,
+    /// 2x4 pixels per cell, one braille dot each
+    Braille,
+    /// 2x2 pixels per cell, drawn as the quadrant block that best splits them into two colors
+    Quadrant,
+    /// 2x3 pixels per cell, drawn as the sextant block that best splits them into two colors
+    Sextant,
+    /// 4x8 pixels per cell, drawn as the charset character whose shape matches them best
+    Shape,
+    /// One pixel per cell, drawn as | / - _ \ where the Sobel gradient reaches --edge-threshold and peaks across the edge, and with the charset elsewhere
+    Edge,
+}
+
+impl RenderMode {
+    /// Pixels per cell horizontally and vertically.
+    pub fn cell_size(&self) -> (i32, i32) {
+        match self {
+            RenderMode::Ramp => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+            RenderMode::Quadrant => (2, 2),
+            RenderMode::Sextant => (2, 3),
+            RenderMode::Shape => glyph::CELL_SIZE,
+            RenderMode::Edge => (1, 1),
+        }
+    }
+
+    pub fn has_opencl_kernel(&self) -> bool {
+        matches!(self, RenderMode::Ramp | RenderMode::Braille | RenderMode::Edge)
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DotMode {
+    /// A dot is on when its pixel is at least as bright as --threshold
+    Threshold,
+    /// Compare every pixel against an 8x8 Bayer matrix instead
+    Ordered,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DitherMode {
+    /// Round to the nearest level
+    None,
+    /// Diffuse the rounding error to the four following neighbours
+    FloydSteinberg,
+    /// Diffuse three quarters of the rounding error to six neighbours, for more contrast
+    Atkinson,
+    /// Offset every pixel by an 8x8 Bayer matrix
+    Bayer,
+    /// Offset every pixel by a 64x64 blue noise mask, without the crosshatch of Bayer
+    BlueNoise,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Equalize {
+    None,
+    /// Spread the brightness of the whole frame evenly over the full range
+    Global,
+    /// Equalize tiles of the frame separately with limited contrast (CLAHE)
+    Clahe,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMode {
+    /// Glyphs in the color of their cell
+    Color,
+    /// Glyphs in the terminal's own colors
+    Grayscale,
+    /// Blank cells painted with their color as background, the glyphs are left out
+    Block,
+    /// Glyphs in the color of their cell on a darker background of the same color
+    Background,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorDepth {
+    Auto,
+    /// 24-bit RGB
+    Truecolor,
+    /// The 6x6x6 color cube and gray ramp of xterm's 256 colors
+    #[value(name = "256")]
+    #[serde(rename = "256")]
+    Ansi256,
+    /// The 16 ANSI colors
+    #[value(name = "16")]
+    #[serde(rename = "16")]
+    Ansi16,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Output {
+    /// Kitty graphics when the terminal answers the protocol's query, iTerm2 images in iTerm2, text otherwise
+    Auto,
+    /// Characters of --mode in the colors of --color
+    Text,
+    /// Sixel graphics, for xterm -ti vt340, foot, mlterm and WezTerm
+    Sixel,
+    /// Images with the kitty graphics protocol, for kitty, Ghostty and WezTerm
+    Kitty,
+    /// PNG images with the iTerm2 inline image protocol, for iTerm2, WezTerm and mintty
+    Iterm,
+}
+
+impl Output {
+    /// Whether frames are drawn as pixels instead of characters.
+    pub fn is_graphics(&self) -> bool {
+        !matches!(self, Output::Auto | Output::Text)
+    }
+
+    /// Most frames per second a video shows when --fps is not given, the others are skipped.
+    pub fn max_fps(&self) -> Option<f64> {
+        match self {
+            Output::Iterm => Some(iterm::MAX_FPS),
+            _ => None,
+        }
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScaleMode {
+    /// Show all of the picture as large as it fits, centered with empty borders
+    Fit,
+    /// Cover the whole output, cropping what does not fit evenly from both sides
+    Fill,
+    /// Cover the whole output with all of the picture, ignoring its aspect ratio
+    Stretch,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    Auto,
+    Cpu,
+    #[value(name = "opencl")]
+    #[serde(rename = "opencl")]
+    OpenCl,
+}
+
+pub fn parse_input(input: &str) -> Result<String, String> {
+    if input.contains("://") || Path::new(input).exists() {
+        return Ok(input.to_string());
+    }
+
+    Err(format!("no such file: {}", input))
+}
+
+/// `value` if it is a finite number above zero, for the setting called `name`.
+pub fn positive(value: f64, name: &str) -> Result<f64, String> {
+    if !value.is_finite() || value <= 0.0 {
+        return Err(format!("{} must be a positive number", name));
+    }
+
+    Ok(value)
+}
+
+pub fn parse_fps(fps: &str) -> Result<f64, String> {
+    positive(fps.parse::<f64>().map_err(|error| error.to_string())?, "frame rate")
+}
+
+pub fn parse_factor(factor: &str) -> Result<f64, String> {
+    positive(factor.parse::<f64>().map_err(|error| error.to_string())?, "factor")
+}
+
+pub fn parse_offset(offset: &str) -> Result<f64, String> {
+    let mut seconds = 0.0;
+    let parts = offset.split(':').collect::<Vec<&str>>();
+    if parts.len() > 3 {
+        return Err("expected seconds or [[hh:]mm:]ss".to_string());
+    }
+
+    for part in parts {
+        let value = part.parse::<f64>().map_err(|_| format!("invalid time: {}", offset))?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(format!("invalid time: {}", offset));
+        }
+
+        seconds = seconds * 60.0 + value;
+    }
+
+    Ok(seconds)
+}
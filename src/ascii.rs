@@ -1,10 +1,13 @@
-use opencv::{core::MatTraitConst, prelude::Mat, core::Vec3b};
+use opencv::{core::MatTraitConst, core::MatTraitConstManual, prelude::Mat};
+use rayon::prelude::*;
 
 use crate::terminal::StringInfo;
 
 pub struct AsciiConverter {
-    chars: Vec<char>,
-    step: u32,
+    char_bytes: Vec<u8>,
+    char_len: u32,
+    // Byte offset into `char_bytes` of the character for every brightness.
+    offsets: [usize; 256],
 }
 
 // ASCII-127 Only
@@ -39,46 +42,324 @@ __kernel void calculate(__global uchar* frame, __global uchar* chars, uint char_
     int char_index = brightness / step;
     write(out, chars, index * char_len, char_index * char_len, char_len);
 }
+
+__inline int brightness_at(__global uchar* frame, uint grayscale, uint width, uint height, int x, int y) {
+    x = clamp(x, 0, (int)width - 1);
+    y = clamp(y, 0, (int)height - 1);
+    int index = y * width + x;
+    if (grayscale) {
+        return frame[index];
+    }
+
+    return (frame[index * 3] + frame[index * 3 + 1] + frame[index * 3 + 2]) / 3;
+}
+
+__inline int2 sobel(__global uchar* frame, uint grayscale, uint width, uint height, int x, int y) {
+    int top_left = brightness_at(frame, grayscale, width, height, x - 1, y - 1);
+    int top = brightness_at(frame, grayscale, width, height, x, y - 1);
+    int top_right = brightness_at(frame, grayscale, width, height, x + 1, y - 1);
+    int left = brightness_at(frame, grayscale, width, height, x - 1, y);
+    int right = brightness_at(frame, grayscale, width, height, x + 1, y);
+    int bottom_left = brightness_at(frame, grayscale, width, height, x - 1, y + 1);
+    int bottom = brightness_at(frame, grayscale, width, height, x, y + 1);
+    int bottom_right = brightness_at(frame, grayscale, width, height, x + 1, y + 1);
+
+    return (int2)(
+        (top_right + 2 * right + bottom_right) - (top_left + 2 * left + bottom_left),
+        (bottom_left + 2 * bottom + bottom_right) - (top_left + 2 * top + top_right)
+    );
+}
+
+// Same bins as `edges::edge_glyph`: 0 |, 1 /, 2 - or _, 3 \.
+__inline int direction(int2 gradient) {
+    int ax = abs(gradient.x);
+    int ay = abs(gradient.y);
+    if (ay * 1000 <= ax * 414) {
+        return 0;
+    }
+    if (ay * 1000 >= ax * 2414) {
+        return 2;
+    }
+
+    return (gradient.x > 0) == (gradient.y > 0) ? 1 : 3;
+}
+
+__inline int magnitude(int2 gradient) {
+    return abs(gradient.x) + abs(gradient.y);
+}
+
+// Same rule as `edges::edge_at`, which the CPU backend uses.
+__kernel void edges(__global uchar* frame, __global uchar* chars, uint char_len, uint grayscale, uint step, uint width, uint height, uint threshold, __global uchar* out) {
+    int index = get_global_id(0);
+    int x = index % width;
+    int y = index / width;
+
+    int char_index = brightness_at(frame, grayscale, width, height, x, y) / step;
+    write(out, chars, index * char_len, char_index * char_len, char_len);
+
+    int2 gradient = sobel(frame, grayscale, width, height, x, y);
+    int strength = magnitude(gradient);
+    if (strength < (int)threshold) {
+        return;
+    }
+
+    // Thin the edges by keeping only the strongest pixel across them.
+    int bin = direction(gradient);
+    int2 offsets[4] = { (int2)(1, 0), (int2)(1, 1), (int2)(0, 1), (int2)(-1, 1) };
+    int2 offset = offsets[bin];
+    if (strength < magnitude(sobel(frame, grayscale, width, height, x + offset.x, y + offset.y))
+        || strength < magnitude(sobel(frame, grayscale, width, height, x - offset.x, y - offset.y))) {
+        return;
+    }
+
+    uchar glyphs[4] = { '|', '/', gradient.y > 0 ? '_' : '-', '\\' };
+    out[index * char_len] = glyphs[bin];
+    for (uint i = 1; i < char_len; i++) {
+        out[index * char_len + i] = 0;
+    }
+}
+
+__constant uchar bayer[64] = {
+     0, 32,  8, 40,  2, 34, 10, 42,
+    48, 16, 56, 24, 50, 18, 58, 26,
+    12, 44,  4, 36, 14, 46,  6, 38,
+    60, 28, 52, 20, 62, 30, 54, 22,
+     3, 35, 11, 43,  1, 33,  9, 41,
+    51, 19, 59, 27, 49, 17, 57, 25,
+    15, 47,  7, 39, 13, 45,  5, 37,
+    63, 31, 55, 23, 61, 29, 53, 21,
+};
+
+__constant uchar dot_bits[8] = { 0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80 };
+
+__kernel void braille(__global uchar* frame, uint width, uint channels, uint threshold, uint ordered, __global uchar* out, __global uchar* colors) {
+    int index = get_global_id(0);
+    uint columns = width / 2;
+    uint cell_x = index % columns;
+    uint cell_y = index / columns;
+
+    uint mask = 0;
+    uint count = 0;
+    uint lit[3] = { 0, 0, 0 };
+    uint all[3] = { 0, 0, 0 };
+    for (uint dy = 0; dy < 4; dy++) {
+        for (uint dx = 0; dx < 2; dx++) {
+            uint x = cell_x * 2 + dx;
+            uint y = cell_y * 4 + dy;
+            uint pixel = (y * width + x) * channels;
+            uint color[3] = { frame[pixel], frame[pixel], frame[pixel] };
+            if (channels == 3) {
+                color[1] = frame[pixel + 1];
+                color[2] = frame[pixel + 2];
+            }
+
+            uint brightness = (color[0] + color[1] + color[2]) / 3;
+            uint dot_threshold = ordered ? bayer[(y % 8) * 8 + x % 8] * 4 + 2 : threshold;
+            for (uint i = 0; i < 3; i++) {
+                all[i] += color[i];
+            }
+            if (brightness >= dot_threshold) {
+                mask |= dot_bits[dy * 2 + dx];
+                count++;
+                for (uint i = 0; i < 3; i++) {
+                    lit[i] += color[i];
+                }
+            }
+        }
+    }
+
+    out[index * 3] = 0xE2;
+    out[index * 3 + 1] = 0xA0 | (mask >> 6);
+    out[index * 3 + 2] = 0x80 | (mask & 0x3F);
+
+    if (count == 0) {
+        count = 8;
+        for (uint i = 0; i < 3; i++) {
+            lit[i] = all[i];
+        }
+    }
+    // The frame is BGR, the colors are RGB.
+    for (uint i = 0; i < 3; i++) {
+        colors[index * 3 + 2 - i] = lit[i] / count;
+    }
+}
 "#;
 
+/// Brightness range covered by each character of a ramp of `char_count` characters.
+/// Rounding 256 rather than 255 up keeps `255 / step` a valid index for every count.
+pub fn step(char_count: usize) -> u32 {
+    (256 + char_count as u32 - 1) / char_count.max(1) as u32
+}
+
+/// Encodes every character of `string` into a slot of the same width, padding shorter
+/// characters with zero bytes, and returns the bytes together with the slot width.
+pub fn encode_chars(string: &str) -> (Vec<u8>, u32) {
+    let char_len = string.chars().map(|x| x.len_utf8()).max().unwrap_or(1) as u32;
+    let mut char_bytes = Vec::<u8>::with_capacity(string.chars().count() * char_len as usize);
+    for char in string.chars() {
+        let mut bytes = vec![0; char_len as usize];
+        char.encode_utf8(&mut bytes);
+        char_bytes.extend_from_slice(&bytes);
+    }
+
+    (char_bytes, char_len)
+}
+
+/// Per-pixel RGB colors of a row-major BGR frame.
+pub fn rgb_colors(frame_bytes: &[u8]) -> Vec<u8> {
+    let mut rgb = vec![0u8; frame_bytes.len()];
+    write_rgb(frame_bytes, &mut rgb);
+
+    rgb
+}
+
+/// Writes the RGB colors of BGR `pixels` into `out`.
+pub fn write_rgb(pixels: &[u8], out: &mut [u8]) {
+    for (color, pixel) in out.chunks_exact_mut(3).zip(pixels.chunks_exact(3)) {
+        color.copy_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+    }
+}
+
+// Rows converted by one rayon task; a handful of rows keeps the tasks big enough to be
+// worth scheduling even for narrow terminals.
+const ROWS_PER_CHUNK: usize = 8;
+
 impl AsciiConverter {
-    pub fn new(string: &String) -> Self {
-        let step = (255.0 / (string.chars().count() as f32)).ceil() as u32;
-        
+    pub fn new(string: &str) -> Self {
+        let (char_bytes, char_len) = encode_chars(string);
+        let step = step(string.chars().count());
+
+        let mut offsets = [0usize; 256];
+        for (brightness, offset) in offsets.iter_mut().enumerate() {
+            *offset = brightness / step as usize * char_len as usize;
+        }
+
         Self {
-            step,
-            chars: string.chars().collect::<Vec<char>>().to_owned(),
+            char_bytes,
+            char_len,
+            offsets,
         }
     }
 
-    pub fn convert(&self, frame: &Mat, grayscale: bool) -> StringInfo {
-        let frame_size = frame.size().unwrap();
-        let mut string = String::with_capacity((frame_size.width * frame_size.height) as usize);
-        let mut rgb = Vec::new();
+    /// Produces the same bytes as the `calculate` kernel in `PROGMRAM`: one zero-padded
+    /// `char_len` slot per pixel, no line breaks, and the pixels as RGB colors.
+    pub fn convert(&self, frame: &Mat) -> Result<StringInfo, String> {
+        let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+        let channels = frame.channels() as usize;
+        let width = frame.cols() as usize;
+        let char_len = self.char_len as usize;
 
-        for row in 0..frame.rows() {
-            string.push('\n');
-            for column in 0..frame.cols() {
-                let brightness: u32;
-                if grayscale {
-                    brightness = (*frame.at_2d::<u8>(row, column).unwrap()) as u32;
-                }
-                else {
-                    let pixel = frame.at_2d::<Vec3b>(row, column).unwrap();
-                    for color in pixel.iter() {
-                        rgb.push(*color);
-                    }
-                    
-                    brightness = pixel.iter().map(|&i| i as u32).sum::<u32>() / 3;
-                }
-                let index = ((brightness as f32) / self.step as f32).floor() - 1.0;
-                string.push(self.chars[index as usize]); }
+        let pixels_per_chunk = (width * ROWS_PER_CHUNK).max(1);
+
+        let mut string = vec![0u8; frame_bytes.len() / channels * char_len];
+        let chunks = string.par_chunks_mut(pixels_per_chunk * char_len).zip(frame_bytes.par_chunks(pixels_per_chunk * channels));
+        let mut rgb = Vec::new();
+        if channels == 1 {
+            chunks.for_each(|(out, pixels)| self.convert_rows(pixels, channels, out, &mut []));
+        }
+        else {
+            rgb = vec![0u8; frame_bytes.len()];
+            chunks.zip(rgb.par_chunks_mut(pixels_per_chunk * 3))
+                .for_each(|((out, pixels), colors)| self.convert_rows(pixels, channels, out, colors));
         }
 
-        StringInfo {
-            string: string.as_bytes().to_vec(),
-            char_len: 0,
+        Ok(StringInfo {
+            string,
+            char_len: self.char_len,
+            width: width as u32,
             rgb,
+            background: Vec::new(),
+        })
+    }
+
+    // `colors` receives the RGB colors of color pixels and is empty for grayscale ones.
+    fn convert_rows(&self, pixels: &[u8], channels: usize, out: &mut [u8], colors: &mut [u8]) {
+        let mut brightness = vec![0u8; pixels.len() / channels];
+        calculate_brightness(pixels, channels, &mut brightness);
+        self.write_glyphs(&brightness, out);
+        if channels != 1 {
+            write_rgb(pixels, colors);
         }
     }
+
+    /// Writes the character of every `brightness` into its slot of `out`.
+    pub fn write_glyphs(&self, brightness: &[u8], out: &mut [u8]) {
+        let char_len = self.char_len as usize;
+        for (glyph, &value) in out.chunks_exact_mut(char_len).zip(brightness.iter()) {
+            let offset = self.offsets[value as usize];
+            glyph.copy_from_slice(&self.char_bytes[offset..offset + char_len]);
+        }
+    }
+}
+
+/// Integer average of the three color channels, exactly like the kernel computes it.
+/// Frames have one or three channels; the fixed stride of the color loop lets the
+/// compiler drop the bounds checks.
+pub fn calculate_brightness(pixels: &[u8], channels: usize, out: &mut [u8]) {
+    if channels == 1 {
+        out.copy_from_slice(pixels);
+        return;
+    }
+
+    for (value, pixel) in out.iter_mut().zip(pixels.chunks_exact(3)) {
+        *value = ((pixel[0] as u16 + pixel[1] as u16 + pixel[2] as u16) / 3) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the `calculate` kernel writes for every pixel of a `channels` frame.
+    fn calculate(frame: &[u8], channels: usize, chars: &str) -> Vec<u8> {
+        let (char_bytes, char_len) = encode_chars(chars);
+        let char_len = char_len as usize;
+        let step = step(chars.chars().count()) as usize;
+        let mut out = Vec::new();
+        for index in 0..frame.len() / channels {
+            let brightness = match channels {
+                1 => frame[index] as usize,
+                _ => (frame[index * 3] as usize + frame[index * 3 + 1] as usize + frame[index * 3 + 2] as usize) / 3,
+            };
+            let char_index = brightness / step;
+            out.extend_from_slice(&char_bytes[char_index * char_len..(char_index + 1) * char_len]);
+        }
+
+        out
+    }
+
+    fn frame(bytes: &[u8], channels: i32, height: i32) -> Mat {
+        Mat::from_slice(bytes).unwrap().reshape(channels, height).unwrap().try_clone().unwrap()
+    }
+
+    #[test]
+    fn converts_like_the_kernel() {
+        // A height that leaves a partial chunk of rows at the end.
+        let (width, height) = (11, ROWS_PER_CHUNK * 2 + 3);
+        for channels in [1, 3] {
+            let bytes = (0..width * height * channels).map(|index| (index * 37 % 256) as u8).collect::<Vec<u8>>();
+            let frame = frame(&bytes, channels as i32, height as i32);
+            for chars in [CHARS1, CHARS2, GRADIENT] {
+                let string = AsciiConverter::new(chars).convert(&frame).unwrap();
+
+                assert_eq!(string.string, calculate(&bytes, channels, chars), "{} channels, {:?}", channels, chars);
+                assert_eq!((string.width, string.char_len), (width as u32, encode_chars(chars).1));
+                let rgb = match channels {
+                    1 => Vec::new(),
+                    _ => bytes.chunks_exact(3).flat_map(|pixel| [pixel[2], pixel[1], pixel[0]]).collect(),
+                };
+                assert_eq!(string.rgb, rgb);
+            }
+        }
+    }
+
+    #[test]
+    fn whole_range_reaches_both_ends_of_the_ramp() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        let string = AsciiConverter::new(GRADIENT).convert(&frame(&bytes, 1, 1)).unwrap();
+        let glyphs = String::from_utf8(string.string.into_iter().filter(|&byte| byte != 0).collect()).unwrap();
+
+        assert_eq!(glyphs.chars().count(), 256);
+        assert!(glyphs.starts_with(' ') && glyphs.ends_with('█'));
+    }
 }
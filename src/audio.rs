@@ -1,37 +1,65 @@
 use std::fs::File;
 use std::io::BufReader;
+use std::time::Duration;
 use crossbeam::channel::Receiver;
-use rodio::{Sink, Decoder, OutputStream};
+use rodio::{Sink, Decoder, OutputStream, Source};
 use crate::controller::Controller;
 
 use crate::event_loop::LoopEvent;
 
 pub struct AudioController<'a> {
     sink: Sink,
+    _stream: OutputStream,
     event_loop_receiver: &'a Receiver<LoopEvent>,
+    path: String,
+    // Position in the file the current source started at; the sink counts from there.
+    offset: Duration,
 }
 
 impl<'a> AudioController<'a> {
-    pub fn new(path: &String, event_loop_receiver: &'a Receiver<LoopEvent>) -> Self {
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let file = BufReader::new(File::open(path.clone()).unwrap());
-        let source = Decoder::new(file).unwrap();
-        let sink = Sink::try_new(&stream_handle).unwrap();
-        sink.append(source);
-
-        Self {
-            sink, 
+    pub fn new(path: &str, start: f64, event_loop_receiver: &'a Receiver<LoopEvent>) -> Result<Self, String> {
+        let (_stream, stream_handle) = OutputStream::try_default().map_err(|error| error.to_string())?;
+        let sink = Sink::try_new(&stream_handle).map_err(|error| error.to_string())?;
+        let offset = Duration::from_secs_f64(start);
+        sink.append(open(path, offset)?);
+
+        Ok(Self {
+            sink,
+            _stream,
             event_loop_receiver,
-        }
+            path: path.to_string(),
+            offset,
+        })
+    }
+
+    /// Plays the file from `position` on, paused if the sink was.
+    fn play_from(&mut self, position: Duration) {
+        let Ok(source) = open(&self.path, position) else {
+            return;
+        };
+        self.sink.stop();
+        self.sink.append(source);
+        self.offset = position;
     }
 }
 
+/// The audio of `path` from `position` on, seeking in the file where the format allows
+/// it and decoding up to there otherwise.
+fn open(path: &str, position: Duration) -> Result<Box<dyn Source<Item = i16> + Send>, String> {
+    let file = BufReader::new(File::open(path).map_err(|error| error.to_string())?);
+    let mut source = Decoder::new(file).map_err(|error| error.to_string())?;
+    if position.is_zero() || source.try_seek(position).is_ok() {
+        return Ok(Box::new(source));
+    }
+
+    Ok(Box::new(source.skip_duration(position)))
+}
+
 impl<'a> Controller for AudioController<'a> {
     fn run(&mut self) {
         self.sink.play();
         loop {
             let event = self.event_loop_receiver.recv().unwrap();
-            println!("audio {:?}", event);
             match event {
                 LoopEvent::PlayPause => {
                     if self.sink.is_paused() {
@@ -41,6 +69,12 @@ impl<'a> Controller for AudioController<'a> {
 
                     self.sink.pause();
                 },
+                // Like the video, which stops at its start when skipping back past it.
+                LoopEvent::Skip(seconds) => {
+                    let position = (self.offset + self.sink.get_pos()).as_secs_f64() + seconds as f64;
+                    self.play_from(Duration::from_secs_f64(position.max(0.0)));
+                },
+                LoopEvent::Restart => self.play_from(Duration::ZERO),
                 LoopEvent::Shutdown => {
                     self.sink.stop();
                     break;
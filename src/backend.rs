@@ -0,0 +1,83 @@
+use opencv::prelude::Mat;
+
+use crate::args::{Backend, RenderMode};
+use crate::ascii::AsciiConverter;
+use crate::blocks::{self, BlockConverter};
+use crate::braille::BrailleConverter;
+use crate::config::Settings;
+use crate::edges::EdgeConverter;
+use crate::glyph::{self, ShapeConverter};
+use crate::opencl::OpenClBackend;
+use crate::terminal::StringInfo;
+
+/// Turns a frame that is already resized to the output size into characters. Frames
+/// with one channel are treated as grayscale, frames with three as BGR color.
+pub trait ConversionBackend {
+    fn convert(&mut self, frame: &Mat) -> Result<StringInfo, String>;
+}
+
+/// The converter of the selected mode.
+enum Converter {
+    Ramp(AsciiConverter),
+    HalfBlock,
+    Braille(BrailleConverter),
+    Block(BlockConverter),
+    Shape(ShapeConverter),
+    Edge(EdgeConverter),
+}
+
+pub struct CpuBackend {
+    converter: Converter,
+}
+
+impl CpuBackend {
+    /// Ramps are drawn with `chars`, as returned by `glyph::chars`.
+    pub fn new(settings: &Settings, chars: &str) -> Result<Self, String> {
+        let converter = match settings.mode {
+            RenderMode::Ramp => Converter::Ramp(AsciiConverter::new(chars)),
+            RenderMode::HalfBlock => Converter::HalfBlock,
+            RenderMode::Braille => Converter::Braille(BrailleConverter::new(settings.threshold, settings.dots)),
+            RenderMode::Quadrant => Converter::Block(BlockConverter::quadrant()),
+            RenderMode::Sextant => Converter::Block(BlockConverter::sextant()),
+            RenderMode::Shape => Converter::Shape(ShapeConverter::new(chars, &glyph::load_font(settings.font.as_deref())?)),
+            RenderMode::Edge => Converter::Edge(EdgeConverter::new(chars, settings.edge_threshold)),
+        };
+
+        Ok(Self { converter })
+    }
+}
+
+impl ConversionBackend for CpuBackend {
+    fn convert(&mut self, frame: &Mat) -> Result<StringInfo, String> {
+        match &self.converter {
+            Converter::Ramp(converter) => converter.convert(frame),
+            Converter::HalfBlock => blocks::half_block(frame),
+            Converter::Braille(converter) => converter.convert(frame),
+            Converter::Block(converter) => converter.convert(frame),
+            Converter::Shape(converter) => converter.convert(frame),
+            Converter::Edge(converter) => converter.convert(frame),
+        }
+    }
+}
+
+pub fn create(settings: &Settings, chars: &str) -> Result<Box<dyn ConversionBackend + Send>, String> {
+    // Modes without a kernel are cheap per pixel and run on the CPU, which should not
+    // happen silently when OpenCL was asked for.
+    if !settings.mode.has_opencl_kernel() {
+        if settings.backend == Backend::OpenCl || settings.device.is_some() {
+            return Err("only the ramp, braille and edge modes run on OpenCL".to_string());
+        }
+        return Ok(Box::new(CpuBackend::new(settings, chars)?));
+    }
+
+    match settings.backend {
+        Backend::Cpu => Ok(Box::new(CpuBackend::new(settings, chars)?)),
+        Backend::OpenCl => Ok(Box::new(OpenClBackend::new(settings, chars)?)),
+        Backend::Auto => match OpenClBackend::new(settings, chars) {
+            Ok(backend) => Ok(Box::new(backend)),
+            // A device that was asked for by name should not silently turn into the CPU.
+            Err(error) if settings.device.is_some() => Err(error),
+            Err(_) => Ok(Box::new(CpuBackend::new(settings, chars)?)),
+        },
+    }
+}
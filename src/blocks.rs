@@ -0,0 +1,240 @@
+use opencv::core::{MatTraitConst, MatTraitConstManual};
+use opencv::prelude::Mat;
+use rayon::prelude::*;
+
+use crate::ascii;
+use crate::terminal::StringInfo;
+
+pub const UPPER_HALF: &str = "▀";
+
+// Quadrant glyph for every mask of upper left (1), upper right (2), lower left (4)
+// and lower right (8) quarters.
+pub const QUADRANTS: [char; 16] = [' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█'];
+
+/// Sextant glyph for a mask of the six 2x3 sixths in row-major order. The Legacy
+/// Computing block leaves out the four shapes that already exist as block elements.
+pub fn sextant(mask: u8) -> char {
+    match mask {
+        0 => ' ',
+        21 => '▌',
+        42 => '▐',
+        63 => '█',
+        _ => char::from_u32(0x1FB00 + mask as u32 - 1 - (mask > 21) as u32 - (mask > 42) as u32).unwrap(),
+    }
+}
+
+/// Draws two vertically stacked pixels per cell. `frame` has twice as many rows as the
+/// output; the top pixel becomes the foreground of `▀` and the bottom one its background.
+pub fn half_block(frame: &Mat) -> Result<StringInfo, String> {
+    let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+    let channels = frame.channels() as usize;
+    let width = frame.cols() as usize;
+    let rows = frame.rows() as usize / 2;
+    let frame_row_len = width * channels;
+
+    let mut rgb = vec![0u8; width * rows * 3];
+    let mut background = vec![0u8; width * rows * 3];
+    rgb.par_chunks_mut((width * 3).max(1))
+        .zip(background.par_chunks_mut((width * 3).max(1)))
+        .enumerate()
+        .for_each(|(row, (top, bottom))| {
+            let top_start = row * 2 * frame_row_len;
+            copy_colors(&frame_bytes[top_start..top_start + frame_row_len], channels, top);
+            copy_colors(&frame_bytes[top_start + frame_row_len..top_start + 2 * frame_row_len], channels, bottom);
+        });
+
+    let glyph = UPPER_HALF.as_bytes();
+    Ok(StringInfo {
+        string: glyph.repeat(width * rows),
+        char_len: glyph.len() as u32,
+        width: width as u32,
+        rgb,
+        background,
+    })
+}
+
+/// Converts a row of BGR or grayscale pixels to RGB colors.
+pub fn copy_colors(pixels: &[u8], channels: usize, out: &mut [u8]) {
+    for (color, pixel) in out.chunks_exact_mut(3).zip(pixels.chunks_exact(channels)) {
+        if channels == 3 {
+            color.copy_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+        else {
+            color.fill(pixel[0]);
+        }
+    }
+}
+
+/// Splits every cell of `columns` x `rows` pixels into the two groups of pixels that
+/// are best described by their average colors, and draws the shape of the first group
+/// in the foreground color over the second one as background.
+pub struct BlockConverter {
+    columns: usize,
+    rows: usize,
+    char_len: usize,
+    // Zero-padded glyph for every mask, `char_len` bytes each.
+    glyphs: Vec<u8>,
+}
+
+impl BlockConverter {
+    pub fn quadrant() -> Self {
+        Self::new(2, 2, &QUADRANTS)
+    }
+
+    pub fn sextant() -> Self {
+        Self::new(2, 3, &(0..64).map(sextant).collect::<Vec<char>>())
+    }
+
+    fn new(columns: usize, rows: usize, glyphs: &[char]) -> Self {
+        let (glyphs, char_len) = ascii::encode_chars(&glyphs.iter().collect::<String>());
+
+        Self {
+            columns,
+            rows,
+            char_len: char_len as usize,
+            glyphs,
+        }
+    }
+
+    pub fn convert(&self, frame: &Mat) -> Result<StringInfo, String> {
+        let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+        let channels = frame.channels() as usize;
+        let width = frame.cols() as usize;
+        let columns = width / self.columns;
+        let rows = frame.rows() as usize / self.rows;
+
+        let mut string = vec![0u8; columns * rows * self.char_len];
+        let mut rgb = vec![0u8; columns * rows * 3];
+        let mut background = vec![0u8; columns * rows * 3];
+        string.par_chunks_mut((columns * self.char_len).max(1))
+            .zip(rgb.par_chunks_mut((columns * 3).max(1)))
+            .zip(background.par_chunks_mut((columns * 3).max(1)))
+            .enumerate()
+            .for_each(|(cell_y, ((glyphs, foreground), background))| {
+                for cell_x in 0..columns {
+                    let mut pixels = [[0u8; 3]; 6];
+                    for (index, pixel) in pixels.iter_mut().take(self.columns * self.rows).enumerate() {
+                        let x = cell_x * self.columns + index % self.columns;
+                        let y = cell_y * self.rows + index / self.columns;
+                        copy_colors(&frame_bytes[(y * width + x) * channels..][..channels], channels, pixel);
+                    }
+
+                    let (mask, fg, bg) = split(&pixels[..self.columns * self.rows]);
+                    let glyph = mask * self.char_len;
+                    glyphs[cell_x * self.char_len..(cell_x + 1) * self.char_len].copy_from_slice(&self.glyphs[glyph..glyph + self.char_len]);
+                    foreground[cell_x * 3..cell_x * 3 + 3].copy_from_slice(&fg);
+                    background[cell_x * 3..cell_x * 3 + 3].copy_from_slice(&bg);
+                }
+            });
+
+        Ok(StringInfo {
+            string,
+            char_len: self.char_len as u32,
+            width: columns as u32,
+            rgb,
+            background,
+        })
+    }
+}
+
+/// Finds the mask of the pixels forming the foreground so that both groups differ the
+/// least from their own average color, together with the two averages. Swapping the
+/// groups gives the same split, so the last pixel always stays in the background.
+fn split(pixels: &[[u8; 3]]) -> (usize, [u8; 3], [u8; 3]) {
+    let mut best_mask = 0;
+    let mut best_score = -1.0f64;
+    for mask in 0..1usize << (pixels.len() - 1) {
+        let (foreground, foreground_count, background, background_count) = sums(pixels, mask);
+        // Minimizing the squared error around the averages is the same as maximizing
+        // the squared sums weighted by group size.
+        let mut score = 0.0;
+        if foreground_count > 0 {
+            score += squared_length(foreground) / foreground_count as f64;
+        }
+        if background_count > 0 {
+            score += squared_length(background) / background_count as f64;
+        }
+
+        if score > best_score {
+            best_score = score;
+            best_mask = mask;
+        }
+    }
+
+    let (foreground, foreground_count, background, background_count) = sums(pixels, best_mask);
+    (best_mask, average(foreground, foreground_count), average(background, background_count))
+}
+
+fn sums(pixels: &[[u8; 3]], mask: usize) -> ([u32; 3], u32, [u32; 3], u32) {
+    let mut foreground = [0u32; 3];
+    let mut background = [0u32; 3];
+    let mut foreground_count = 0;
+    let mut background_count = 0;
+    for (index, pixel) in pixels.iter().enumerate() {
+        let (sum, count) = if mask >> index & 1 == 1 {
+            (&mut foreground, &mut foreground_count)
+        }
+        else {
+            (&mut background, &mut background_count)
+        };
+
+        *count += 1;
+        for (total, &value) in sum.iter_mut().zip(pixel.iter()) {
+            *total += value as u32;
+        }
+    }
+
+    (foreground, foreground_count, background, background_count)
+}
+
+fn squared_length(sum: [u32; 3]) -> f64 {
+    sum.iter().map(|&x| (x as f64) * (x as f64)).sum()
+}
+
+fn average(sum: [u32; 3], count: u32) -> [u8; 3] {
+    if count == 0 {
+        return [0; 3];
+    }
+
+    [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sextants_skip_the_block_elements() {
+        assert_eq!([sextant(0), sextant(21), sextant(42), sextant(63)], [' ', '▌', '▐', '█']);
+        // BLOCK SEXTANT-1 to BLOCK SEXTANT-23456, the first and last of the block.
+        assert_eq!([sextant(1), sextant(20), sextant(22), sextant(41), sextant(43), sextant(62)], ['🬀', '🬓', '🬔', '🬧', '🬨', '🬻']);
+
+        let mut glyphs = (0..64).map(sextant).collect::<Vec<char>>();
+        glyphs.sort();
+        glyphs.dedup();
+        assert_eq!(glyphs.len(), 64);
+    }
+
+    #[test]
+    fn split_separates_two_colors() {
+        let (red, blue) = ([200, 0, 0], [0, 0, 200]);
+        let (mask, foreground, background) = split(&[red, red, blue, blue]);
+        assert_eq!((QUADRANTS[mask], foreground, background), ('▀', red, blue));
+
+        // The last pixel stays in the background, so the glyph is the inverse shape.
+        let (mask, foreground, background) = split(&[blue, red, red, red, red, red]);
+        assert_eq!((sextant(mask as u8), foreground, background), ('🬀', blue, red));
+    }
+
+    #[test]
+    fn split_averages_each_group() {
+        let (mask, foreground, background) = split(&[[250, 250, 250], [240, 240, 240], [10, 20, 30], [20, 30, 40]]);
+        assert_eq!((mask, foreground, background), (0b0011, [245, 245, 245], [15, 25, 35]));
+    }
+
+    #[test]
+    fn one_color_is_all_background() {
+        let gray = [90, 90, 90];
+        assert_eq!(split(&[gray; 6]), (0, [0, 0, 0], gray));
+    }
+}
@@ -0,0 +1,218 @@
+use opencv::core::{MatTraitConst, MatTraitConstManual};
+use opencv::prelude::Mat;
+use rayon::prelude::*;
+
+use crate::args::DotMode;
+use crate::dither::BAYER;
+use crate::terminal::StringInfo;
+
+// Bit of the braille code point for the dot at [row][column] of a cell.
+pub const DOT_BITS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// UTF-8 bytes of U+2800 plus `mask`, every braille pattern is three bytes long.
+pub fn encode(mask: u8) -> [u8; 3] {
+    [0xE2, 0xA0 | (mask >> 6), 0x80 | (mask & 0x3F)]
+}
+
+/// Draws 2x4 pixels per cell as the dots of a braille pattern, colored with the
+/// average of the dots that are on. Matches the `braille` kernel in `ascii::PROGMRAM`.
+pub struct BrailleConverter {
+    threshold: u8,
+    dots: DotMode,
+}
+
+impl BrailleConverter {
+    pub fn new(threshold: u8, dots: DotMode) -> Self {
+        Self {
+            threshold,
+            dots,
+        }
+    }
+
+    pub fn convert(&self, frame: &Mat) -> Result<StringInfo, String> {
+        let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+        let channels = frame.channels() as usize;
+        let width = frame.cols() as usize;
+        let columns = width / 2;
+        let rows = frame.rows() as usize / 4;
+
+        let mut string = vec![0u8; columns * rows * 3];
+        let mut rgb = vec![0u8; columns * rows * 3];
+        string.par_chunks_mut((columns * 3).max(1))
+            .zip(rgb.par_chunks_mut((columns * 3).max(1)))
+            .enumerate()
+            .for_each(|(cell_y, (glyphs, colors))| {
+                for cell_x in 0..columns {
+                    let (mask, color) = self.cell(frame_bytes, width, channels, cell_x, cell_y);
+                    glyphs[cell_x * 3..cell_x * 3 + 3].copy_from_slice(&encode(mask));
+                    colors[cell_x * 3..cell_x * 3 + 3].copy_from_slice(&color);
+                }
+            });
+
+        if channels == 1 {
+            rgb = Vec::new();
+        }
+
+        Ok(StringInfo {
+            string,
+            char_len: 3,
+            width: columns as u32,
+            rgb,
+            background: Vec::new(),
+        })
+    }
+
+    fn cell(&self, frame_bytes: &[u8], width: usize, channels: usize, cell_x: usize, cell_y: usize) -> (u8, [u8; 3]) {
+        let mut mask = 0u8;
+        let mut count = 0u32;
+        let mut lit = [0u32; 3];
+        let mut all = [0u32; 3];
+
+        for (dy, bits) in DOT_BITS.iter().enumerate() {
+            for (dx, bit) in bits.iter().enumerate() {
+                let x = cell_x * 2 + dx;
+                let y = cell_y * 4 + dy;
+                let pixel = &frame_bytes[(y * width + x) * channels..][..channels];
+                let color = if channels == 1 { [pixel[0]; 3] } else { [pixel[2], pixel[1], pixel[0]] };
+                let brightness = (color[0] as u32 + color[1] as u32 + color[2] as u32) / 3;
+                let threshold = match self.dots {
+                    DotMode::Threshold => self.threshold as u32,
+                    DotMode::Ordered => BAYER[y % 8][x % 8] as u32 * 4 + 2,
+                };
+
+                for channel in 0..3 {
+                    all[channel] += color[channel] as u32;
+                }
+                if brightness >= threshold {
+                    mask |= bit;
+                    count += 1;
+                    for channel in 0..3 {
+                        lit[channel] += color[channel] as u32;
+                    }
+                }
+            }
+        }
+
+        if count == 0 {
+            count = 8;
+            lit = all;
+        }
+
+        (mask, [(lit[0] / count) as u8, (lit[1] / count) as u8, (lit[2] / count) as u8])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii;
+
+    /// The numbers of the `__constant` table `name` in `ascii::PROGMRAM`.
+    fn kernel_table(name: &str) -> Vec<u32> {
+        let start = ascii::PROGMRAM.find(&format!("uchar {}[", name)).unwrap();
+        let table = &ascii::PROGMRAM[start..];
+        let table = &table[table.find('{').unwrap() + 1..table.find('}').unwrap()];
+        table.split(',').map(str::trim).filter(|number| !number.is_empty())
+            .map(|number| match number.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).unwrap(),
+                None => number.parse().unwrap(),
+            })
+            .collect()
+    }
+
+    /// What the `braille` kernel writes for every cell of a `channels` frame: the glyphs,
+    /// and the colors it would hand over for a color frame.
+    fn braille(frame: &[u8], width: usize, channels: usize, threshold: u32, ordered: bool) -> (Vec<u8>, Vec<u8>) {
+        let bayer = kernel_table("bayer");
+        let dot_bits = kernel_table("dot_bits");
+        let columns = width / 2;
+        let rows = frame.len() / channels / width / 4;
+        let (mut out, mut colors) = (Vec::new(), Vec::new());
+        for index in 0..columns * rows {
+            let (cell_x, cell_y) = (index % columns, index / columns);
+            let (mut mask, mut count) = (0, 0);
+            let (mut lit, mut all) = ([0u32; 3], [0u32; 3]);
+            for dy in 0..4 {
+                for dx in 0..2 {
+                    let (x, y) = (cell_x * 2 + dx, cell_y * 4 + dy);
+                    let pixel = (y * width + x) * channels;
+                    let mut color = [frame[pixel] as u32; 3];
+                    if channels == 3 {
+                        color[1] = frame[pixel + 1] as u32;
+                        color[2] = frame[pixel + 2] as u32;
+                    }
+
+                    let brightness = (color[0] + color[1] + color[2]) / 3;
+                    let dot_threshold = if ordered { bayer[(y % 8) * 8 + x % 8] * 4 + 2 } else { threshold };
+                    for i in 0..3 {
+                        all[i] += color[i];
+                    }
+                    if brightness >= dot_threshold {
+                        mask |= dot_bits[dy * 2 + dx];
+                        count += 1;
+                        for i in 0..3 {
+                            lit[i] += color[i];
+                        }
+                    }
+                }
+            }
+
+            out.extend_from_slice(&[0xE2, 0xA0 | (mask >> 6) as u8, 0x80 | (mask & 0x3F) as u8]);
+            if count == 0 {
+                count = 8;
+                lit = all;
+            }
+            colors.extend_from_slice(&[(lit[2] / count) as u8, (lit[1] / count) as u8, (lit[0] / count) as u8]);
+        }
+
+        (out, colors)
+    }
+
+    fn frame(bytes: &[u8], channels: i32, height: i32) -> Mat {
+        Mat::from_slice(bytes).unwrap().reshape(channels, height).unwrap().try_clone().unwrap()
+    }
+
+    #[test]
+    fn converts_like_the_kernel() {
+        // Rows past the last whole cell are left out, like the kernel does.
+        let (width, height) = (10, 14);
+        for channels in [1, 3] {
+            let bytes = (0..width * height * channels).map(|index| (index * 53 % 256) as u8).collect::<Vec<u8>>();
+            let frame = frame(&bytes, channels as i32, height as i32);
+            for (dots, ordered) in [(DotMode::Threshold, false), (DotMode::Ordered, true)] {
+                let string = BrailleConverter::new(100, dots).convert(&frame).unwrap();
+                let (glyphs, colors) = braille(&bytes, width, channels, 100, ordered);
+
+                assert_eq!(string.string, glyphs, "{} channels, {:?}", channels, dots);
+                assert_eq!((string.width, string.char_len), (width as u32 / 2, 3));
+                assert_eq!(string.rgb, if channels == 1 { Vec::new() } else { colors }, "{} channels, {:?}", channels, dots);
+            }
+        }
+    }
+
+    #[test]
+    fn every_dot_has_its_own_bit() {
+        // Dots 1 to 8 of the braille patterns, left column first, by row and column.
+        let dots = [['⠁', '⠈'], ['⠂', '⠐'], ['⠄', '⠠'], ['⡀', '⢀']];
+        for (row, bits) in DOT_BITS.iter().enumerate() {
+            for (column, &bit) in bits.iter().enumerate() {
+                let mut bytes = [0u8; 8];
+                bytes[row * 2 + column] = 255;
+                let string = BrailleConverter::new(128, DotMode::Threshold).convert(&frame(&bytes, 1, 4)).unwrap();
+
+                assert_eq!(string.string, encode(bit));
+                assert_eq!(std::str::from_utf8(&string.string).unwrap().chars().next(), Some(dots[row][column]));
+            }
+        }
+        assert_eq!(DOT_BITS.iter().flatten().fold(0, |mask, &bit| mask | bit), 0xFF);
+    }
+
+    #[test]
+    fn encodes_every_pattern() {
+        for mask in 0..=255u8 {
+            let encoded = encode(mask);
+            let char = char::from_u32(0x2800 + mask as u32).unwrap();
+            assert_eq!(std::str::from_utf8(&encoded).unwrap(), char.to_string());
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::args::{self, Args, Backend, Charset, ColorDepth, ColorMode, DitherMode, DotMode, Equalize, Output, RenderMode, ScaleMode};
+use crate::palette;
+use crate::terminal;
+
+/// One layer of options, as found at the top level of the config file or in a
+/// `[profile.<name>]` table. Keys are the long command-line flag names.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Options {
+    pub input: Option<String>,
+    pub charset: Option<Charset>,
+    pub chars: Option<String>,
+    pub ramp_levels: Option<u16>,
+    pub dedup: Option<bool>,
+    pub mode: Option<RenderMode>,
+    pub font: Option<PathBuf>,
+    pub dots: Option<DotMode>,
+    pub threshold: Option<u8>,
+    pub edge_threshold: Option<u16>,
+    pub dither: Option<DitherMode>,
+    pub stable_dither: Option<bool>,
+    pub color_levels: Option<u16>,
+    pub brightness: Option<i16>,
+    pub contrast: Option<f64>,
+    pub gamma: Option<f64>,
+    pub invert: Option<bool>,
+    pub equalize: Option<Equalize>,
+    pub color: Option<ColorMode>,
+    pub color_depth: Option<ColorDepth>,
+    pub output: Option<Output>,
+    pub backend: Option<Backend>,
+    pub device: Option<String>,
+    pub scale: Option<ScaleMode>,
+    pub cell_aspect: Option<f64>,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub fps: Option<f64>,
+    pub start: Option<Offset>,
+    #[serde(rename = "loop")]
+    pub looping: Option<bool>,
+    pub mute: Option<bool>,
+}
+
+/// `start` may be written either as seconds or as a `[[hh:]mm:]ss` string.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Offset {
+    Seconds(f64),
+    Time(String),
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Profile applied when none is given with `--profile`.
+    pub default_profile: Option<String>,
+    #[serde(rename = "profile")]
+    pub profiles: HashMap<String, Options>,
+    #[serde(flatten)]
+    pub options: Options,
+}
+
+/// Options after merging, in order of precedence, command-line flags (and the `THE_*`
+/// environment variables clap reads for them), the selected profile, the top level of
+/// the config file and the built-in defaults.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub input: String,
+    pub charset: Charset,
+    pub chars: Option<String>,
+    pub ramp_levels: Option<u16>,
+    pub dedup: bool,
+    pub mode: RenderMode,
+    pub font: Option<PathBuf>,
+    pub dots: DotMode,
+    pub threshold: u8,
+    pub edge_threshold: u16,
+    pub dither: DitherMode,
+    pub stable_dither: bool,
+    pub color_levels: Option<u16>,
+    pub brightness: i16,
+    pub contrast: f64,
+    pub gamma: f64,
+    pub invert: bool,
+    pub equalize: Equalize,
+    pub color_mode: ColorMode,
+    // Never `Auto` after `Settings::detect_terminal`, which detects the depth of the terminal.
+    pub color_depth: ColorDepth,
+    // Never `Auto` after `Settings::detect_terminal`, which asks the terminal what it can draw.
+    pub output: Output,
+    pub backend: Backend,
+    pub device: Option<String>,
+    pub scale: ScaleMode,
+    pub cell_aspect: Option<f64>,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub fps: Option<f64>,
+    pub start: f64,
+    pub looping: bool,
+    pub mute: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            input: String::new(),
+            charset: Charset::Chars3,
+            chars: None,
+            ramp_levels: None,
+            dedup: false,
+            mode: RenderMode::Ramp,
+            font: None,
+            dots: DotMode::Threshold,
+            threshold: 128,
+            edge_threshold: 100,
+            dither: DitherMode::None,
+            stable_dither: false,
+            color_levels: None,
+            brightness: 0,
+            contrast: 1.0,
+            gamma: 1.0,
+            invert: false,
+            equalize: Equalize::None,
+            color_mode: ColorMode::Color,
+            color_depth: ColorDepth::Auto,
+            output: Output::Text,
+            backend: Backend::Auto,
+            device: None,
+            scale: ScaleMode::Fit,
+            cell_aspect: None,
+            width: None,
+            height: None,
+            fps: None,
+            start: 0.0,
+            looping: false,
+            mute: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: Option<&PathBuf>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.clone(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let text = fs::read_to_string(&path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        toml::from_str(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+}
+
+impl Settings {
+    pub fn new(args: Args) -> Result<Self, String> {
+        let config = Config::load(args.config.as_ref())?;
+        Self::merge(args, &config)
+    }
+
+    fn merge(args: Args, config: &Config) -> Result<Self, String> {
+        let profile_name = args.profile.as_ref().or(config.default_profile.as_ref());
+        let profile = match profile_name {
+            Some(name) => config.profiles.get(name).cloned().ok_or(format!("no profile named {} in config file", name))?,
+            None => Options::default(),
+        };
+        let file = &config.options;
+
+        let input = args.input.or(profile.input).or(file.input.clone()).ok_or("no input given, see --help")?;
+        let start = match args.start {
+            Some(start) => start,
+            None => match profile.start.as_ref().or(file.start.as_ref()) {
+                Some(Offset::Seconds(seconds)) if *seconds >= 0.0 => *seconds,
+                Some(Offset::Seconds(_)) => return Err("start must not be negative".to_string()),
+                Some(Offset::Time(time)) => args::parse_offset(time)?,
+                None => 0.0,
+            },
+        };
+
+        let defaults = Self::default();
+        let settings = Self {
+            input: args::parse_input(&input)?,
+            charset: args.charset.or(profile.charset).or(file.charset).unwrap_or(defaults.charset),
+            chars: args.chars.or(profile.chars).or(file.chars.clone()),
+            ramp_levels: args.ramp_levels.or(profile.ramp_levels).or(file.ramp_levels),
+            dedup: args.dedup.or(profile.dedup).or(file.dedup).unwrap_or(defaults.dedup),
+            mode: args.mode.or(profile.mode).or(file.mode).unwrap_or(defaults.mode),
+            font: args.font.or(profile.font).or(file.font.clone()),
+            dots: args.dots.or(profile.dots).or(file.dots).unwrap_or(defaults.dots),
+            threshold: args.threshold.or(profile.threshold).or(file.threshold).unwrap_or(defaults.threshold),
+            edge_threshold: args.edge_threshold.or(profile.edge_threshold).or(file.edge_threshold).unwrap_or(defaults.edge_threshold),
+            dither: args.dither.or(profile.dither).or(file.dither).unwrap_or(defaults.dither),
+            stable_dither: args.stable_dither.or(profile.stable_dither).or(file.stable_dither).unwrap_or(defaults.stable_dither),
+            color_levels: args.color_levels.or(profile.color_levels).or(file.color_levels),
+            brightness: args.brightness.or(profile.brightness).or(file.brightness).unwrap_or(defaults.brightness),
+            contrast: args.contrast.or(profile.contrast).or(file.contrast).unwrap_or(defaults.contrast),
+            gamma: args.gamma.or(profile.gamma).or(file.gamma).unwrap_or(defaults.gamma),
+            invert: args.invert.or(profile.invert).or(file.invert).unwrap_or(defaults.invert),
+            equalize: args.equalize.or(profile.equalize).or(file.equalize).unwrap_or(defaults.equalize),
+            color_mode: args.color_mode.or(profile.color).or(file.color).unwrap_or(defaults.color_mode),
+            color_depth: args.color_depth.or(profile.color_depth).or(file.color_depth).unwrap_or(defaults.color_depth),
+            output: args.output.or(profile.output).or(file.output).unwrap_or(defaults.output),
+            backend: args.backend.or(profile.backend).or(file.backend).unwrap_or(defaults.backend),
+            device: args.device.or(profile.device).or(file.device.clone()),
+            scale: args.scale.or(profile.scale).or(file.scale).unwrap_or(defaults.scale),
+            cell_aspect: args.cell_aspect.or(profile.cell_aspect).or(file.cell_aspect),
+            width: args.width.or(profile.width).or(file.width),
+            height: args.height.or(profile.height).or(file.height),
+            fps: args.fps.or(profile.fps).or(file.fps),
+            start,
+            looping: args.looping.or(profile.looping).or(file.looping).unwrap_or(defaults.looping),
+            mute: args.mute.or(profile.mute).or(file.mute).unwrap_or(defaults.mute),
+        };
+
+        if settings.chars.as_ref().is_some_and(|chars| chars.is_empty()) {
+            return Err("chars must not be empty".to_string());
+        }
+        // Both work on the custom characters, a charset is used as it is.
+        if settings.chars.is_none() && (settings.ramp_levels.is_some() || settings.dedup) {
+            return Err("ramp-levels and dedup need chars".to_string());
+        }
+        if settings.ramp_levels.is_some_and(|levels| levels < 2) {
+            return Err("ramp-levels must be at least 2".to_string());
+        }
+        if settings.color_levels.is_some_and(|levels| !(2..=256).contains(&levels)) {
+            return Err("color-levels must be between 2 and 256".to_string());
+        }
+        if settings.width == Some(0) || settings.height == Some(0) {
+            return Err("width and height must be at least 1".to_string());
+        }
+        if !(-255..=255).contains(&settings.brightness) {
+            return Err("brightness must be between -255 and 255".to_string());
+        }
+        args::positive(settings.contrast, "contrast")?;
+        args::positive(settings.gamma, "gamma")?;
+        if let Some(cell_aspect) = settings.cell_aspect {
+            args::positive(cell_aspect, "cell-aspect")?;
+        }
+        if let Some(fps) = settings.fps {
+            args::positive(fps, "fps")?;
+        }
+
+        Ok(settings)
+    }
+
+    /// Asks the terminal what it supports in place of the `Auto` color depth and output.
+    pub fn detect_terminal(&mut self) {
+        if self.color_depth == ColorDepth::Auto {
+            self.color_depth = palette::detect();
+        }
+        if self.output == Output::Auto {
+            self.output = terminal::detect_output();
+        }
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_home.join("the").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn settings(flags: &[&str], config: &str) -> Result<Settings, String> {
+        let args = Args::try_parse_from(["the", "https://example.com/video.mp4"].iter().chain(flags)).map_err(|error| error.to_string())?;
+        let config = toml::from_str::<Config>(config).map_err(|error| error.to_string())?;
+        Settings::merge(args, &config)
+    }
+
+    #[test]
+    fn flags_then_environment_then_profile_then_file_then_defaults() {
+        let config = "threshold = 10\n[profile.dark]\nthreshold = 20\n";
+        assert_eq!(settings(&[], "").unwrap().threshold, 128);
+        assert_eq!(settings(&[], config).unwrap().threshold, 10);
+        assert_eq!(settings(&["--profile", "dark"], config).unwrap().threshold, 20);
+
+        // No other test reads the threshold, which clap takes from the environment.
+        env::set_var("THE_THRESHOLD", "30");
+        let from_environment = settings(&["--profile", "dark"], config).map(|settings| settings.threshold);
+        let from_flag = settings(&["--profile", "dark", "--threshold", "40"], config).map(|settings| settings.threshold);
+        env::remove_var("THE_THRESHOLD");
+        assert_eq!(from_environment, Ok(30));
+        assert_eq!(from_flag, Ok(40));
+    }
+
+    #[test]
+    fn default_profile_applies_unless_another_is_given() {
+        let config = "default-profile = \"blocks\"\nmode = \"edge\"\n[profile.blocks]\nmode = \"sextant\"\n[profile.dots]\nmode = \"braille\"\n";
+        assert_eq!(settings(&[], config).unwrap().mode, RenderMode::Sextant);
+        assert_eq!(settings(&["--profile", "dots"], config).unwrap().mode, RenderMode::Braille);
+        assert!(settings(&["--profile", "missing"], config).is_err());
+        assert!(settings(&[], "default-profile = \"missing\"\n").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(settings(&[], "tresholds = 10\n").unwrap_err().contains("tresholds"));
+        assert!(settings(&[], "[profile.dark]\ntresholds = 10\n").unwrap_err().contains("tresholds"));
+    }
+
+    #[test]
+    fn numbers_are_validated() {
+        assert!(settings(&[], "contrast = 1.5\ngamma = 0.8\ncell-aspect = 2.1\nfps = 24\n").is_ok());
+        for config in ["contrast = 0.0\n", "gamma = -1.0\n", "cell-aspect = nan\n", "fps = inf\n", "ramp-levels = 1\nchars = \"ab\"\n"] {
+            assert!(settings(&[], config).is_err(), "{}", config);
+        }
+        assert!(settings(&["--dedup"], "").is_err());
+    }
+
+    #[test]
+    fn auto_is_left_for_the_terminal() {
+        let settings = settings(&["--color-depth", "auto", "--output", "auto"], "").unwrap();
+        assert_eq!((settings.color_depth, settings.output), (ColorDepth::Auto, Output::Auto));
+    }
+}
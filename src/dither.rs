@@ -0,0 +1,411 @@
+use std::sync::OnceLock;
+
+use opencv::core::{MatTraitConst, MatTraitManual};
+use opencv::prelude::Mat;
+
+use crate::args::{ColorDepth, ColorMode, DitherMode, Output, RenderMode};
+use crate::ascii;
+use crate::config::Settings;
+use crate::palette;
+
+// Where the error of a pixel goes as (dx, dy, weight).
+const FLOYD_STEINBERG: [(isize, usize, f32); 4] = [(1, 0, 7.0 / 16.0), (-1, 1, 3.0 / 16.0), (0, 1, 5.0 / 16.0), (1, 1, 1.0 / 16.0)];
+const ATKINSON: [(isize, usize, f32); 6] = [(1, 0, 0.125), (2, 0, 0.125), (-1, 1, 0.125), (0, 1, 0.125), (1, 1, 0.125), (0, 2, 0.125)];
+
+/// Ranks 0 to 63 of the pixels of an 8x8 tile for ordered dithering, by [row][column].
+pub const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// Side of the tileable blue noise mask.
+const BLUE_NOISE_SIZE: usize = 64;
+
+// With stable dithering a pixel keeps its previous output until its source has moved
+// this far from the value it was last quantized from.
+const STABLE_TOLERANCE: f32 = 6.0;
+
+#[derive(Clone, Copy)]
+enum Quantizer {
+    // The average of the channels to one of `levels` evenly spaced levels from 0 to 255,
+    // shifting all channels alike so the hue is kept. Rounded down, level `n` lies in the
+    // brightness range of the `n`th character of a ramp with `ascii::step(..)` as step.
+    Brightness { levels: usize },
+    // Every channel on its own to evenly spaced levels from 0 to 255.
+    Channels { spacing: f32 },
+    // The pixel to the perceptually closest color of a 256 or 16 color palette. Offsets
+    // move every channel by `spread`, about the distance between the palette's levels.
+    Palette { depth: ColorDepth, spread: f32 },
+}
+
+impl Quantizer {
+    /// Writes the closest representable pixel to `wanted` into `out`. `offset` moves the
+    /// decision between two levels and is given in fractions of the distance between them.
+    fn quantize(&self, wanted: &[f32], offset: f32, out: &mut [u8]) {
+        match *self {
+            Quantizer::Brightness { levels } => {
+                let last = (levels - 1) as f32;
+                let brightness = wanted.iter().sum::<f32>() / wanted.len() as f32;
+                let level = (brightness * last / 255.0 + 0.5 + offset).floor().clamp(0.0, last);
+                let shift = (level * 255.0 / last).floor() - brightness;
+                for (out, value) in out.iter_mut().zip(wanted.iter()) {
+                    *out = (value + shift).round().clamp(0.0, 255.0) as u8;
+                }
+            },
+            Quantizer::Channels { spacing } => {
+                for (out, value) in out.iter_mut().zip(wanted.iter()) {
+                    *out = ((value / spacing + offset).round() * spacing).round().clamp(0.0, 255.0) as u8;
+                }
+            },
+            Quantizer::Palette { depth, spread } => {
+                let channel = |index: usize| (wanted[index] + offset * spread).round().clamp(0.0, 255.0) as u8;
+                // Frames are in BGR order, palettes in RGB.
+                let [r, g, b] = palette::nearest(depth, [channel(2), channel(1), channel(0)]);
+                out.copy_from_slice(&[b, g, r]);
+            },
+        }
+    }
+}
+
+struct Pass {
+    quantizer: Quantizer,
+    // For stable dithering, the source every pixel was last quantized from and its output.
+    anchors: Vec<u8>,
+    previous: Vec<u8>,
+}
+
+impl Pass {
+    fn new(quantizer: Quantizer) -> Self {
+        Self {
+            quantizer,
+            anchors: Vec::new(),
+            previous: Vec::new(),
+        }
+    }
+
+    fn ordered(&self, bytes: &mut [u8], width: usize, channels: usize, threshold: impl Fn(usize, usize) -> f32) {
+        let mut wanted = [0.0f32; 3];
+        for (index, pixel) in bytes.chunks_exact_mut(channels).enumerate() {
+            for (wanted, &value) in wanted.iter_mut().zip(pixel.iter()) {
+                *wanted = value as f32;
+            }
+            self.quantizer.quantize(&wanted[..channels], threshold(index % width, index / width), pixel);
+        }
+    }
+
+    fn diffuse(&mut self, bytes: &mut [u8], width: usize, channels: usize, kernel: &[(isize, usize, f32)], stable: bool) {
+        let height = bytes.len() / channels / width.max(1);
+        let stable = stable && self.previous.len() == bytes.len();
+        if !stable {
+            self.anchors = bytes.to_vec();
+            self.previous = vec![0; bytes.len()];
+        }
+
+        let mut wanted = bytes.iter().map(|&value| value as f32).collect::<Vec<f32>>();
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) * channels;
+                let source = &bytes[index..index + channels];
+                let mut out = [0u8; 3];
+                let keep = stable && source.iter().zip(self.anchors[index..].iter())
+                    .all(|(&value, &anchor)| (value as f32 - anchor as f32).abs() < STABLE_TOLERANCE);
+                if keep {
+                    out[..channels].copy_from_slice(&self.previous[index..index + channels]);
+                }
+                else {
+                    self.anchors[index..index + channels].copy_from_slice(source);
+                    self.quantizer.quantize(&wanted[index..index + channels], 0.0, &mut out[..channels]);
+                }
+
+                for channel in 0..channels {
+                    let error = wanted[index + channel] - out[channel] as f32;
+                    for &(dx, dy, weight) in kernel {
+                        let (next_x, next_y) = (x as isize + dx, y + dy);
+                        if next_x >= 0 && (next_x as usize) < width && next_y < height {
+                            wanted[(next_y * width + next_x as usize) * channels + channel] += error * weight;
+                        }
+                    }
+                }
+
+                self.previous[index..index + channels].copy_from_slice(&out[..channels]);
+                bytes[index..index + channels].copy_from_slice(&out[..channels]);
+            }
+        }
+    }
+}
+
+/// Quantizes resized frames before they are converted to characters: brightness to the
+/// levels of the ramp, so that small charsets do not band, and colors to --color-levels
+/// and to the palette of a terminal without truecolor.
+pub struct Ditherer {
+    mode: DitherMode,
+    stable: bool,
+    passes: Vec<Pass>,
+}
+
+impl Ditherer {
+    /// `None` when there is nothing to quantize with the settings. Brightness is
+    /// quantized to the levels of the ramp `chars`.
+    pub fn new(settings: &Settings, chars: &str) -> Option<Self> {
+        let mut passes = Vec::new();
+
+        // Without dithering the ramp already picks the nearest level on its own. Blocks leave
+        // the glyphs out, so the pass would only band their colors.
+        let glyphs = settings.mode == RenderMode::Ramp && settings.color_mode != ColorMode::Block && !settings.output.is_graphics();
+        if settings.dither != DitherMode::None && glyphs {
+            // A long ramp can have more characters than steps of its size fit into 256.
+            let levels = 255 / ascii::step(chars.chars().count()) as usize + 1;
+            if levels > 1 {
+                passes.push(Pass::new(Quantizer::Brightness { levels }));
+            }
+        }
+
+        // Colors are quantized last so that they end up exactly on their levels.
+        let colored = settings.color_mode != ColorMode::Grayscale;
+        if let Some(levels) = settings.color_levels.filter(|_| colored) {
+            passes.push(Pass::new(Quantizer::Channels { spacing: 255.0 / (levels - 1) as f32 }));
+        }
+
+        // Without dithering the terminal encoder picks the nearest palette color on its own.
+        // Sixel images always use the 256-color palette, other images are in truecolor.
+        let depth = match settings.output {
+            Output::Auto | Output::Text => settings.color_depth,
+            Output::Sixel => ColorDepth::Ansi256,
+            Output::Kitty | Output::Iterm => ColorDepth::Truecolor,
+        };
+        let spread = match depth {
+            ColorDepth::Ansi256 => Some(255.0 / 6.0),
+            ColorDepth::Ansi16 => Some(255.0 / 3.0),
+            ColorDepth::Auto | ColorDepth::Truecolor => None,
+        };
+        if let Some(spread) = spread.filter(|_| colored && settings.dither != DitherMode::None) {
+            passes.push(Pass::new(Quantizer::Palette { depth, spread }));
+        }
+
+        if passes.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            mode: settings.dither,
+            stable: settings.stable_dither,
+            passes,
+        })
+    }
+
+    pub fn apply(&mut self, frame: &mut Mat) -> Result<(), String> {
+        let width = frame.cols() as usize;
+        let channels = frame.channels() as usize;
+        let bytes = frame.data_bytes_mut().map_err(|error| error.message)?;
+
+        for pass in self.passes.iter_mut() {
+            match self.mode {
+                DitherMode::None => pass.ordered(bytes, width, channels, |_, _| 0.0),
+                DitherMode::FloydSteinberg => pass.diffuse(bytes, width, channels, &FLOYD_STEINBERG, self.stable),
+                DitherMode::Atkinson => pass.diffuse(bytes, width, channels, &ATKINSON, self.stable),
+                DitherMode::Bayer => pass.ordered(bytes, width, channels, |x, y| (BAYER[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5),
+                DitherMode::BlueNoise => {
+                    let mask = blue_noise();
+                    pass.ordered(bytes, width, channels, |x, y| mask[y % BLUE_NOISE_SIZE * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE]);
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Thresholds from -0.5 to 0.5 for a tileable square whose pixels below any threshold are
+/// spread out evenly without clumps, ranked with Ulichney's void-and-cluster method.
+fn blue_noise() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let pixels = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let mut pattern = Pattern::new();
+
+        // Start from a sparse random pattern and move points from the tightest cluster to
+        // the largest void until that would put the point back where it came from.
+        let mut seed = 0x2545F491u32;
+        for _ in 0..pixels / 10 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let index = seed as usize % pixels;
+            if !pattern.points[index] {
+                pattern.toggle(index);
+            }
+        }
+        loop {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // The initial points are ranked by taking them out tightest first, everything
+        // else by filling the largest void.
+        let mut ranks = vec![0usize; pixels];
+        let initial = pattern.clone();
+        let count = pattern.points.iter().filter(|&&point| point).count();
+        for rank in (0..count).rev() {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            ranks[cluster] = rank;
+        }
+        pattern = initial;
+        for rank in count..pixels {
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            ranks[void] = rank;
+        }
+
+        ranks.iter().map(|&rank| (rank as f32 + 0.5) / pixels as f32 - 0.5).collect()
+    })
+}
+
+#[derive(Clone)]
+struct Pattern {
+    points: Vec<bool>,
+    // Sum of a gaussian around every point, wrapping around the edges.
+    energy: Vec<f32>,
+    splat: Vec<f32>,
+}
+
+impl Pattern {
+    fn new() -> Self {
+        let size = BLUE_NOISE_SIZE;
+        let sigma = 1.5f32;
+        let mut splat = vec![0.0; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let (dx, dy) = (x.min(size - x) as f32, y.min(size - y) as f32);
+                splat[y * size + x] = (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+            }
+        }
+
+        Self {
+            points: vec![false; size * size],
+            energy: vec![0.0; size * size],
+            splat,
+        }
+    }
+
+    fn toggle(&mut self, index: usize) {
+        let size = BLUE_NOISE_SIZE;
+        let sign = if self.points[index] { -1.0 } else { 1.0 };
+        self.points[index] = !self.points[index];
+
+        let (point_x, point_y) = (index % size, index / size);
+        for y in 0..size {
+            for x in 0..size {
+                let offset = (y + size - point_y) % size * size + (x + size - point_x) % size;
+                self.energy[y * size + x] += sign * self.splat[offset];
+            }
+        }
+    }
+
+    fn tightest_cluster(&self) -> usize {
+        (0..self.points.len()).filter(|&index| self.points[index])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap_or(0)
+    }
+
+    fn largest_void(&self) -> usize {
+        (0..self.points.len()).filter(|&index| !self.points[index])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brightness_levels_fall_into_the_ramp_ranges() {
+        for chars in [2, 5, 10, 16, 67, 92] {
+            let step = ascii::step(chars) as usize;
+            let levels = 255 / step + 1;
+            let quantizer = Quantizer::Brightness { levels };
+            let outputs = (0..=255u8)
+                .map(|brightness| {
+                    let mut out = [0u8];
+                    quantizer.quantize(&[brightness as f32], 0.0, &mut out);
+                    out[0]
+                })
+                .collect::<Vec<u8>>();
+
+            // The extremes are reached, and every level is one of its own range.
+            assert_eq!((outputs[0], outputs[255]), (0, 255), "{} chars", chars);
+            let mut distinct = outputs.clone();
+            distinct.dedup();
+            assert_eq!(distinct.len(), levels, "{} chars", chars);
+            for (level, &value) in distinct.iter().enumerate() {
+                assert_eq!(value as usize / step, level, "{} chars", chars);
+            }
+            // Without an offset every brightness goes to the closest level.
+            for (brightness, &value) in outputs.iter().enumerate() {
+                let closest = distinct.iter().map(|&level| (level as i32 - brightness as i32).abs()).min().unwrap();
+                assert_eq!((value as i32 - brightness as i32).abs(), closest, "{} chars, brightness {}", chars, brightness);
+            }
+        }
+    }
+
+    #[test]
+    fn stable_diffusion_keeps_pixels_that_barely_moved() {
+        let width = 4;
+        let mut pass = Pass::new(Quantizer::Brightness { levels: 2 });
+        let mut first = vec![100u8; width * 4];
+        pass.diffuse(&mut first, width, 1, &FLOYD_STEINBERG, true);
+        assert!(first.iter().all(|&value| value == 0 || value == 255));
+        assert!(first.contains(&0) && first.contains(&255));
+
+        // Moves below the tolerance keep the previous output, even where the diffused
+        // error would now round the other way.
+        let mut second = vec![100 + STABLE_TOLERANCE as u8 - 1; width * 4];
+        second[5] = 250;
+        pass.diffuse(&mut second, width, 1, &FLOYD_STEINBERG, true);
+        for (index, (&kept, &previous)) in second.iter().zip(first.iter()).enumerate() {
+            if index == 5 {
+                assert_eq!(kept, 255);
+            }
+            else {
+                assert_eq!(kept, previous, "pixel {}", index);
+            }
+        }
+
+        // Anchors stay at the value last quantized from, so a slow drift is picked up
+        // once it adds up to the tolerance.
+        let mut third = vec![100 + 2 * STABLE_TOLERANCE as u8; width * 4];
+        third[5] = 250;
+        pass.diffuse(&mut third, width, 1, &FLOYD_STEINBERG, true);
+        assert_eq!(pass.anchors[0], 100 + 2 * STABLE_TOLERANCE as u8);
+        assert_eq!(pass.anchors[5], 250);
+    }
+
+    #[test]
+    fn stable_diffusion_starts_over_for_another_size() {
+        let mut pass = Pass::new(Quantizer::Brightness { levels: 2 });
+        let mut frame = vec![100u8; 16];
+        pass.diffuse(&mut frame, 4, 1, &FLOYD_STEINBERG, true);
+        let mut larger = vec![102u8; 25];
+        pass.diffuse(&mut larger, 5, 1, &FLOYD_STEINBERG, true);
+        assert_eq!(pass.anchors, vec![102u8; 25]);
+    }
+
+    #[test]
+    fn brightness_keeps_the_hue() {
+        let mut out = [0u8; 3];
+        Quantizer::Brightness { levels: 2 }.quantize(&[20.0, 60.0, 100.0], 0.0, &mut out);
+        assert_eq!(out, [0, 0, 40]);
+    }
+}
@@ -0,0 +1,227 @@
+use opencv::core::{MatTraitConst, MatTraitConstManual};
+use opencv::prelude::Mat;
+use rayon::prelude::*;
+
+use crate::ascii::{self, AsciiConverter};
+use crate::terminal::StringInfo;
+
+/// Character for an edge across which the brightness changes by `gx` horizontally and
+/// `gy` vertically. The edge runs perpendicular to that gradient, and `_` is used for
+/// horizontal edges that get brighter downwards. Matches `direction` in `ascii::PROGMRAM`.
+pub fn edge_glyph(gx: i32, gy: i32) -> u8 {
+    let (ax, ay) = (gx.abs(), gy.abs());
+    if ay * 1000 <= ax * 414 {
+        b'|'
+    }
+    else if ay * 1000 >= ax * 2414 {
+        if gy > 0 { b'_' } else { b'-' }
+    }
+    else if (gx > 0) == (gy > 0) {
+        b'/'
+    }
+    else {
+        b'\\'
+    }
+}
+
+/// Sobel gradient of a `width` x `height` grid of `brightness` at `x`, `y`, reading
+/// pixels past the border from the nearest one inside, like `sobel` in `ascii::PROGMRAM`.
+fn sobel(brightness: &[u8], width: usize, height: usize, x: isize, y: isize) -> (i32, i32) {
+    let at = |dx: isize, dy: isize| {
+        let x = (x + dx).clamp(0, width as isize - 1) as usize;
+        let y = (y + dy).clamp(0, height as isize - 1) as usize;
+        brightness[y * width + x] as i32
+    };
+
+    (
+        (at(1, -1) + 2 * at(1, 0) + at(1, 1)) - (at(-1, -1) + 2 * at(-1, 0) + at(-1, 1)),
+        (at(-1, 1) + 2 * at(0, 1) + at(1, 1)) - (at(-1, -1) + 2 * at(0, -1) + at(1, -1)),
+    )
+}
+
+fn magnitude(gradient: (i32, i32)) -> i32 {
+    gradient.0.abs() + gradient.1.abs()
+}
+
+/// Sobel gradient of every pixel of a `width` x `height` grid of `brightness`.
+pub fn gradients(brightness: &[u8], width: usize, height: usize) -> Vec<(i32, i32)> {
+    (0..width * height).into_par_iter()
+        .map(|index| sobel(brightness, width, height, (index % width) as isize, (index / width) as isize))
+        .collect()
+}
+
+/// Glyph of the pixel at `x`, `y` when it lies on an edge, by the same rule as the `edges`
+/// kernel: a gradient of at least `threshold` that no neighbour across the edge exceeds.
+/// There is no hysteresis like in Canny, every pixel is decided on its own.
+pub fn edge_at(gradients: &[(i32, i32)], brightness: &[u8], width: usize, height: usize, x: usize, y: usize, threshold: u16) -> Option<u8> {
+    let gradient = gradients[y * width + x];
+    let strength = magnitude(gradient);
+    if strength < threshold as i32 {
+        return None;
+    }
+
+    // Thin the edges by keeping only the strongest pixel across them. Like the kernel,
+    // neighbours past the border get the gradient of their clamped surroundings.
+    let glyph = edge_glyph(gradient.0, gradient.1);
+    let (dx, dy) = match glyph {
+        b'|' => (1, 0),
+        b'/' => (1, 1),
+        b'\\' => (-1, 1),
+        _ => (0, 1),
+    };
+    let neighbour = |x: isize, y: isize| {
+        if (0..width as isize).contains(&x) && (0..height as isize).contains(&y) {
+            magnitude(gradients[y as usize * width + x as usize])
+        }
+        else {
+            magnitude(sobel(brightness, width, height, x, y))
+        }
+    };
+    let (x, y) = (x as isize, y as isize);
+    if strength < neighbour(x + dx, y + dy) || strength < neighbour(x - dx, y - dy) {
+        return None;
+    }
+
+    Some(glyph)
+}
+
+/// Draws pixels on edges with a line in the direction of the edge, and everything else
+/// with the brightness ramp. Produces the same bytes as the `edges` kernel.
+pub struct EdgeConverter {
+    ascii_converter: AsciiConverter,
+    char_len: usize,
+    threshold: u16,
+}
+
+impl EdgeConverter {
+    pub fn new(chars: &str, threshold: u16) -> Self {
+        Self {
+            ascii_converter: AsciiConverter::new(chars),
+            char_len: ascii::encode_chars(chars).1 as usize,
+            threshold,
+        }
+    }
+
+    pub fn convert(&self, frame: &Mat) -> Result<StringInfo, String> {
+        let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+        let channels = frame.channels() as usize;
+        let width = frame.cols() as usize;
+        let height = frame.rows() as usize;
+        let row_len = width.max(1);
+
+        let mut brightness = vec![0u8; width * height];
+        brightness.par_chunks_mut(row_len)
+            .zip(frame_bytes.par_chunks(row_len * channels))
+            .for_each(|(out, pixels)| ascii::calculate_brightness(pixels, channels, out));
+        let gradients = gradients(&brightness, width, height);
+
+        let char_len = self.char_len;
+        let mut string = vec![0u8; width * height * char_len];
+        string.par_chunks_mut(row_len * char_len).enumerate().for_each(|(y, row)| {
+            self.ascii_converter.write_glyphs(&brightness[y * width..(y + 1) * width], row);
+            for (x, glyph) in row.chunks_exact_mut(char_len).enumerate() {
+                if let Some(edge) = edge_at(&gradients, &brightness, width, height, x, y, self.threshold) {
+                    glyph.fill(0);
+                    glyph[0] = edge;
+                }
+            }
+        });
+
+        let mut rgb = Vec::new();
+        if channels != 1 {
+            rgb = vec![0u8; frame_bytes.len()];
+            rgb.par_chunks_mut(row_len * 3)
+                .zip(frame_bytes.par_chunks(row_len * 3))
+                .for_each(|(colors, pixels)| ascii::write_rgb(pixels, colors));
+        }
+
+        Ok(StringInfo {
+            string,
+            char_len: char_len as u32,
+            width: width as u32,
+            rgb,
+            background: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges(brightness: &[u8], width: usize, threshold: u16) -> Vec<Option<u8>> {
+        let height = brightness.len() / width;
+        let gradients = gradients(brightness, width, height);
+        (0..brightness.len()).map(|index| edge_at(&gradients, brightness, width, height, index % width, index / width, threshold)).collect()
+    }
+
+    #[test]
+    fn vertical_step_is_drawn_on_both_sides() {
+        let (width, height) = (8, 6);
+        let brightness = (0..width * height).map(|index| if index % width < 4 { 0 } else { 255 }).collect::<Vec<u8>>();
+
+        for (index, edge) in edges(&brightness, width, 100).into_iter().enumerate() {
+            let expected = if index % width == 3 || index % width == 4 { Some(b'|') } else { None };
+            assert_eq!(edge, expected, "pixel {}, {}", index % width, index / width);
+        }
+    }
+
+    #[test]
+    fn horizontal_step_points_at_the_brighter_side() {
+        let (width, height) = (5, 8);
+        let brighter_below = (0..width * height).map(|index| if index / width < 4 { 0 } else { 255 }).collect::<Vec<u8>>();
+        let brighter_above = brighter_below.iter().map(|&value| 255 - value).collect::<Vec<u8>>();
+
+        for (brightness, glyph) in [(brighter_below, b'_'), (brighter_above, b'-')] {
+            for (index, edge) in edges(&brightness, width, 100).into_iter().enumerate() {
+                let expected = if index / width == 3 || index / width == 4 { Some(glyph) } else { None };
+                assert_eq!(edge, expected, "pixel {}, {}", index % width, index / width);
+            }
+        }
+    }
+
+    #[test]
+    fn weak_steps_stay_below_the_threshold() {
+        let width = 8;
+        // A step of 20 gives a gradient of 4 * 20 = 80.
+        let brightness = (0..width * 6).map(|index| if index % width < 4 { 100 } else { 120 }).collect::<Vec<u8>>();
+        assert!(edges(&brightness, width, 81).iter().all(Option::is_none));
+        assert!(edges(&brightness, width, 80).iter().any(Option::is_some));
+    }
+
+    #[test]
+    fn only_the_strongest_pixel_across_a_ramp_is_kept() {
+        let width = 9;
+        // The gradient is strongest at x = 4, where the brightness rises most steeply.
+        let row = [0u8, 0, 10, 40, 120, 200, 230, 240, 240];
+        let brightness = row.iter().cycle().take(width * 5).copied().collect::<Vec<u8>>();
+
+        for (index, edge) in edges(&brightness, width, 100).into_iter().enumerate() {
+            let expected = if index % width == 4 { Some(b'|') } else { None };
+            assert_eq!(edge, expected, "pixel {}, {}", index % width, index / width);
+        }
+    }
+
+    #[test]
+    fn converter_draws_edges_over_the_ramp() {
+        let (width, height) = (6, 3);
+        // Blue on the left, white on the right, in BGR.
+        let bytes = (0..width * height).flat_map(|index| if index % width < 3 { [255, 0, 0] } else { [255, 255, 255] }).collect::<Vec<u8>>();
+        let frame = Mat::from_slice(&bytes).unwrap().reshape(3, height as i32).unwrap().try_clone().unwrap();
+        let string = EdgeConverter::new(ascii::GRADIENT, 100).convert(&frame).unwrap();
+
+        let (char_bytes, char_len) = ascii::encode_chars(ascii::GRADIENT);
+        let char_len = char_len as usize;
+        for (index, glyph) in string.string.chunks_exact(char_len).enumerate() {
+            let expected = match index % width {
+                2 | 3 => vec![b'|', 0, 0],
+                // A brightness of 85 is the second character of the ramp.
+                0 | 1 => char_bytes[char_len..2 * char_len].to_vec(),
+                _ => char_bytes[4 * char_len..].to_vec(),
+            };
+            assert_eq!(glyph, expected, "pixel {}, {}", index % width, index / width);
+        }
+        assert_eq!(string.rgb[..3], [0, 0, 255]);
+        assert_eq!(string.rgb.len(), width * height * 3);
+    }
+}
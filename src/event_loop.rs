@@ -1,24 +1,42 @@
-use std::io::{stdin, stdout};
+use std::io::stdin;
+use std::process;
+use std::thread;
 
-use crossbeam::channel::Sender;
-use termion::{input::TermRead, raw::IntoRawMode};
+use crossbeam::channel::{select, unbounded, Receiver, Sender};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGWINCH};
+use signal_hook::iterator::Signals;
+use termion::input::TermRead;
+use crate::adjust::Adjustment;
 use crate::controller::Controller;
+use crate::terminal;
 
 #[derive(Debug, Clone, Copy)]
 pub enum LoopEvent {
     PlayPause,
     Skip(i32),
+    Adjust(Adjustment),
+    // The terminal changed its size.
+    Resize,
+    // The video started over with --loop.
+    Restart,
     Shutdown,
 }
 
 pub struct EventLoopController<'a> {
     event_loop_senders: &'a[Sender<LoopEvent>],
+    // Tells the loop to stop when the player ends without a key, like at the end of a video.
+    event_loop_receiver: &'a Receiver<LoopEvent>,
+    // Registered up front so that a failure is reported before the terminal is taken over;
+    // the loop takes them when it starts.
+    signals: Option<Signals>,
 }
 
 impl<'a> EventLoopController<'a> {
-    pub fn new(event_loop_senders: &'a[Sender<LoopEvent>]) -> Self {
-        Self { event_loop_senders }
-    } 
+    pub fn new(event_loop_senders: &'a[Sender<LoopEvent>], event_loop_receiver: &'a Receiver<LoopEvent>) -> Result<Self, String> {
+        let signals = Signals::new([SIGWINCH, SIGHUP, SIGINT, SIGTERM]).map_err(|error| error.to_string())?;
+
+        Ok(Self { event_loop_senders, event_loop_receiver, signals: Some(signals) })
+    }
 
     fn send(&self, event: LoopEvent) {
         for x in self.event_loop_senders {
@@ -30,23 +48,79 @@ impl<'a> EventLoopController<'a> {
 
 impl<'a> Controller for EventLoopController<'a> {
     fn run(&mut self) {
-        let _stdout = stdout().into_raw_mode().unwrap();
-        let mut keys = stdin().keys();
-
-        loop {
-            let key = keys.next().unwrap();
-            let event = match key.unwrap() {
-                termion::event::Key::Char(' ') | termion::event::Key::Char('k') => { LoopEvent::PlayPause },
-                termion::event::Key::Char('j') => { LoopEvent::Skip(-10) },
-                termion::event::Key::Char('l') => { LoopEvent::Skip(10) },
-                termion::event::Key::Ctrl('c') => {
-                    self.send(LoopEvent::Shutdown);
+        let Some(mut signals) = self.signals.take() else {
+            return;
+        };
+        let signals_handle = signals.handle();
+        let this = &*self;
+
+        // Reading a key blocks until one is pressed, so keys are read on a thread that is
+        // left behind when the loop stops for another reason.
+        let (key_sender, key_receiver) = unbounded();
+        thread::spawn(move || {
+            for key in stdin().keys() {
+                if key_sender.send(key).is_err() {
                     break;
                 }
-                _ => { continue; },
-            };
+            }
+        });
 
-            self.send(event);
-        }
+        thread::scope(|scope| {
+            // Resizes arrive as a signal instead of being polled for.
+            scope.spawn(move || {
+                for signal in signals.forever() {
+                    if signal == SIGWINCH {
+                        this.send(LoopEvent::Resize);
+                        continue;
+                    }
+
+                    // Ctrl-c is a key in raw mode, so these come from outside, and the loop
+                    // below would not notice them while it waits for a key.
+                    terminal::restore();
+                    process::exit(128 + signal);
+                }
+            });
+
+            loop {
+                let key = select! {
+                    recv(key_receiver) -> key => key,
+                    recv(this.event_loop_receiver) -> event => match event {
+                        Ok(LoopEvent::Shutdown) | Err(_) => break,
+                        _ => continue,
+                    },
+                };
+                let key = match key {
+                    Ok(Ok(key)) => key,
+                    // Without input there is no way to quit anymore.
+                    _ => {
+                        this.send(LoopEvent::Shutdown);
+                        break;
+                    },
+                };
+                let event = match key {
+                    termion::event::Key::Char(' ') | termion::event::Key::Char('k') => { LoopEvent::PlayPause },
+                    termion::event::Key::Char('j') => { LoopEvent::Skip(-10) },
+                    termion::event::Key::Char('l') => { LoopEvent::Skip(10) },
+                    termion::event::Key::Char('b') => { LoopEvent::Adjust(Adjustment::Brightness(-1)) },
+                    termion::event::Key::Char('B') => { LoopEvent::Adjust(Adjustment::Brightness(1)) },
+                    termion::event::Key::Char('c') => { LoopEvent::Adjust(Adjustment::Contrast(-1)) },
+                    termion::event::Key::Char('C') => { LoopEvent::Adjust(Adjustment::Contrast(1)) },
+                    termion::event::Key::Char('g') => { LoopEvent::Adjust(Adjustment::Gamma(-1)) },
+                    termion::event::Key::Char('G') => { LoopEvent::Adjust(Adjustment::Gamma(1)) },
+                    termion::event::Key::Char('i') => { LoopEvent::Adjust(Adjustment::Invert) },
+                    termion::event::Key::Char('e') => { LoopEvent::Adjust(Adjustment::Equalize) },
+                    termion::event::Key::Char('r') => { LoopEvent::Adjust(Adjustment::Reset) },
+                    termion::event::Key::Ctrl('c') => {
+                        this.send(LoopEvent::Shutdown);
+                        break;
+                    }
+                    _ => { continue; },
+                };
+
+                this.send(event);
+            }
+
+            signals_handle.close();
+        });
     }
 }
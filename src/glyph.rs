@@ -0,0 +1,239 @@
+use std::fs;
+use std::path::Path;
+
+use fontdue::{Font, FontSettings};
+use opencv::core::{MatTraitConst, MatTraitConstManual};
+use opencv::prelude::Mat;
+use rayon::prelude::*;
+
+use crate::ascii;
+use crate::blocks::copy_colors;
+use crate::config::Settings;
+use crate::terminal::StringInfo;
+
+pub const DEFAULT_FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+
+// Glyphs and cells are compared on a coarse grid of regions per cell.
+pub const GRID_WIDTH: usize = 2;
+pub const GRID_HEIGHT: usize = 4;
+const GRID_SIZE: usize = GRID_WIDTH * GRID_HEIGHT;
+
+// Brightness levels a region of a cell is quantized to before looking up its glyph.
+const LEVELS: usize = 4;
+
+// Pixels per cell, every grid region averages 2x2 pixels.
+pub const CELL_SIZE: (i32, i32) = (2 * GRID_WIDTH as i32, 2 * GRID_HEIGHT as i32);
+
+// Pixel height glyphs are rasterized at before being averaged down to the grid.
+const RASTER_SIZE: f32 = 32.0;
+
+pub fn load_font(path: Option<&Path>) -> Result<Font, String> {
+    let font = match path {
+        Some(path) => Font::from_bytes(fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?, FontSettings::default()),
+        None => Font::from_bytes(DEFAULT_FONT, FontSettings::default()),
+    };
+
+    font.map_err(|error| format!("could not load font: {}", error))
+}
+
+/// Ink coverage of `char` in each region of the grid, from 0 for empty to 255 for
+/// completely filled, measured within a cell of the font's advance and line height.
+pub fn coverage(font: &Font, char: char) -> [f32; GRID_SIZE] {
+    let mut grid = [0.0; GRID_SIZE];
+    let Some(line) = font.horizontal_line_metrics(RASTER_SIZE) else {
+        return grid;
+    };
+    let cell_width = font.metrics('M', RASTER_SIZE).advance_width;
+    let cell_height = line.ascent - line.descent;
+    if cell_width <= 0.0 || cell_height <= 0.0 {
+        return grid;
+    }
+
+    let (metrics, bitmap) = font.rasterize(char, RASTER_SIZE);
+    let top = line.ascent - (metrics.ymin as f32 + metrics.height as f32);
+    for y in 0..metrics.height {
+        for x in 0..metrics.width {
+            let cell_x = metrics.xmin as f32 + x as f32 + 0.5;
+            let cell_y = top + y as f32 + 0.5;
+            let grid_x = ((cell_x / cell_width * GRID_WIDTH as f32) as isize).clamp(0, GRID_WIDTH as isize - 1) as usize;
+            let grid_y = ((cell_y / cell_height * GRID_HEIGHT as f32) as isize).clamp(0, GRID_HEIGHT as isize - 1) as usize;
+            grid[grid_y * GRID_WIDTH + grid_x] += bitmap[y * metrics.width + x] as f32;
+        }
+    }
+
+    let region_area = cell_width * cell_height / GRID_SIZE as f32;
+    grid.map(|sum| (sum / region_area).min(255.0))
+}
+
+// Custom characters whose ink differs by less than this are duplicates for `--dedup`.
+const DEDUP_TOLERANCE: f32 = 1.0;
+
+/// Characters to draw with: the selected charset, or the custom characters sorted
+/// from least to most ink when `--chars` is given.
+pub fn chars(settings: &Settings) -> Result<String, String> {
+    match &settings.chars {
+        Some(chars) => Ok(density_ramp(chars, &load_font(settings.font.as_deref())?, settings.dedup, settings.ramp_levels.map(|x| x as usize))),
+        None => Ok(settings.charset.chars().to_string()),
+    }
+}
+
+/// Orders `chars` by their average ink coverage in `font`. With `dedup` characters
+/// that are about as dark as the previous one are dropped, and with `levels` the
+/// result is resampled to that many characters with evenly spaced coverage.
+pub fn density_ramp(chars: &str, font: &Font, dedup: bool, levels: Option<usize>) -> String {
+    let mut glyphs = Vec::<(char, f32)>::new();
+    for char in chars.chars() {
+        if !glyphs.iter().any(|(x, _)| *x == char) {
+            glyphs.push((char, coverage(font, char).iter().sum::<f32>() / GRID_SIZE as f32));
+        }
+    }
+    glyphs.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    if dedup {
+        glyphs.dedup_by(|next, kept| (next.1 - kept.1).abs() < DEDUP_TOLERANCE);
+    }
+
+    if let Some(levels) = levels {
+        let lightest = glyphs[0].1;
+        let darkest = glyphs[glyphs.len() - 1].1;
+        glyphs = (0..levels)
+            .map(|level| {
+                let target = lightest + (darkest - lightest) * level as f32 / (levels - 1) as f32;
+                *glyphs.iter().min_by(|a, b| (a.1 - target).abs().total_cmp(&(b.1 - target).abs())).unwrap()
+            })
+            .collect();
+    }
+
+    glyphs.iter().map(|(char, _)| char).collect()
+}
+
+/// Picks for every cell the character of the charset whose rasterized shape is closest
+/// to the cell's pixels. Every possible quantized cell is matched once up front, so
+/// converting a frame is a table lookup per cell.
+pub struct ShapeConverter {
+    char_bytes: Vec<u8>,
+    char_len: usize,
+    // Index of the best character for every key of quantized region levels.
+    lookup: Vec<u16>,
+}
+
+impl ShapeConverter {
+    pub fn new(chars: &str, font: &Font) -> Self {
+        let (char_bytes, char_len) = ascii::encode_chars(chars);
+        let mut coverages = chars.chars().map(|char| coverage(font, char)).collect::<Vec<[f32; GRID_SIZE]>>();
+
+        // Like a brightness ramp, the densest character stands for full brightness even
+        // when its ink covers only part of the cell.
+        let densest = coverages.iter().flatten().fold(0.0f32, |a, &b| a.max(b));
+        if densest > 0.0 {
+            for glyph in coverages.iter_mut() {
+                *glyph = glyph.map(|value| value * 255.0 / densest);
+            }
+        }
+
+        let lookup = (0..LEVELS.pow(GRID_SIZE as u32)).into_par_iter()
+            .map(|key| {
+                let cell = levels(key);
+                coverages.iter()
+                    .map(|glyph| cell.iter().zip(glyph.iter()).map(|(a, b)| (a - b) * (a - b)).sum::<f32>())
+                    .enumerate()
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(index, _)| index as u16)
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        Self {
+            char_bytes,
+            char_len: char_len as usize,
+            lookup,
+        }
+    }
+
+    pub fn convert(&self, frame: &Mat) -> Result<StringInfo, String> {
+        let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+        let channels = frame.channels() as usize;
+        let width = frame.cols() as usize;
+        let (cell_width, cell_height) = (CELL_SIZE.0 as usize, CELL_SIZE.1 as usize);
+        let columns = width / cell_width;
+        let rows = frame.rows() as usize / cell_height;
+
+        let mut string = vec![0u8; columns * rows * self.char_len];
+        let mut rgb = vec![0u8; columns * rows * 3];
+        string.par_chunks_mut((columns * self.char_len).max(1))
+            .zip(rgb.par_chunks_mut((columns * 3).max(1)))
+            .enumerate()
+            .for_each(|(cell_y, (glyphs, colors))| {
+                let mut pixels = [[0u8; 3]; (CELL_SIZE.0 * CELL_SIZE.1) as usize];
+                for cell_x in 0..columns {
+                    for (index, pixel) in pixels.iter_mut().enumerate() {
+                        let x = cell_x * cell_width + index % cell_width;
+                        let y = cell_y * cell_height + index / cell_width;
+                        copy_colors(&frame_bytes[(y * width + x) * channels..][..channels], channels, pixel);
+                    }
+
+                    let (key, color) = quantize(&pixels);
+                    let glyph = self.lookup[key] as usize * self.char_len;
+                    glyphs[cell_x * self.char_len..(cell_x + 1) * self.char_len].copy_from_slice(&self.char_bytes[glyph..glyph + self.char_len]);
+                    colors[cell_x * 3..cell_x * 3 + 3].copy_from_slice(&color);
+                }
+            });
+
+        if channels == 1 {
+            rgb = Vec::new();
+        }
+
+        Ok(StringInfo {
+            string,
+            char_len: self.char_len as u32,
+            width: columns as u32,
+            rgb,
+            background: Vec::new(),
+        })
+    }
+}
+
+fn levels(key: usize) -> [f32; GRID_SIZE] {
+    let mut cell = [0.0; GRID_SIZE];
+    for (index, value) in cell.iter_mut().enumerate() {
+        let level = key / LEVELS.pow(index as u32) % LEVELS;
+        *value = (level * 255 / (LEVELS - 1)) as f32;
+    }
+
+    cell
+}
+
+/// Key of the quantized region levels of a cell, and the average color of its pixels
+/// that are at least as bright as the cell, which is what the glyph's ink stands for.
+fn quantize(pixels: &[[u8; 3]]) -> (usize, [u8; 3]) {
+    let cell_width = CELL_SIZE.0 as usize;
+    let mut brightness = [0u32; (CELL_SIZE.0 * CELL_SIZE.1) as usize];
+    for (value, pixel) in brightness.iter_mut().zip(pixels.iter()) {
+        *value = (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3;
+    }
+
+    let mut regions = [0u32; GRID_SIZE];
+    for (index, value) in brightness.iter().enumerate() {
+        let (x, y) = (index % cell_width, index / cell_width);
+        regions[y / 2 * GRID_WIDTH + x / 2] += value;
+    }
+
+    let step = 255 / (LEVELS as u32 - 1);
+    let key = regions.iter().enumerate()
+        .map(|(index, sum)| (((sum / 4 + step / 2) / step).min(LEVELS as u32 - 1)) as usize * LEVELS.pow(index as u32))
+        .sum();
+
+    let mean = brightness.iter().sum::<u32>() / brightness.len() as u32;
+    let mut sum = [0u32; 3];
+    let mut count = 0;
+    for (pixel, value) in pixels.iter().zip(brightness.iter()) {
+        if *value >= mean {
+            count += 1;
+            for (total, &channel) in sum.iter_mut().zip(pixel.iter()) {
+                *total += channel as u32;
+            }
+        }
+    }
+
+    (key, sum.map(|total| (total / count.max(1)) as u8))
+}
@@ -0,0 +1,25 @@
+use std::io::{self, Write};
+
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ExtendedColorType, ImageEncoder};
+
+use crate::base64;
+use crate::terminal::PixelFrame;
+
+// Videos show no more than this many frames per second unless --fps is given, every
+// frame is a whole PNG the terminal has to decode.
+pub const MAX_FPS: f64 = 12.0;
+
+/// Writes `frame` as a PNG with the iTerm2 inline image protocol, with its top left corner
+/// at the cursor and sized to the cells it covers. The PNG is compressed fast rather than
+/// small, so that it is ready in time for the next frame.
+pub fn encode<W: Write>(out: &mut W, frame: &PixelFrame) -> io::Result<()> {
+    let mut png = Vec::new();
+    PngEncoder::new_with_quality(&mut png, CompressionType::Fast, FilterType::Sub)
+        .write_image(&frame.rgb, frame.width, frame.height, ExtendedColorType::Rgb8)
+        .map_err(io::Error::other)?;
+
+    write!(out, "\x1B]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:", png.len(), frame.columns, frame.rows)?;
+    out.write_all(&base64::encode(&png))?;
+    out.write_all(b"\x07")
+}
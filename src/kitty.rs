@@ -0,0 +1,43 @@
+use std::io::{self, Write};
+
+use crate::base64;
+use crate::terminal::PixelFrame;
+
+// Every frame replaces the image and the placement with these ids, so that frames do
+// not pile up on top of each other.
+const IMAGE_ID: u32 = 1;
+const PLACEMENT_ID: u32 = 1;
+
+// Base64 bytes per escape sequence, the most the protocol allows.
+const CHUNK_SIZE: usize = 4096;
+
+/// Asks whether the terminal knows the protocol by querying a 1x1 image with an id of
+/// its own, which gets an OK back from terminals that do.
+pub const QUERY: &[u8] = b"\x1B_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1B\\";
+pub const QUERY_OK: &[u8] = b"\x1B_Gi=31;OK\x1B\\";
+
+/// Writes `frame` as an image with the kitty graphics protocol, with its top left corner
+/// at the cursor, in place of the frame before it. The cursor stays where it is and the
+/// terminal is told not to answer, so nothing shows up on stdin.
+pub fn encode<W: Write>(out: &mut W, frame: &PixelFrame) -> io::Result<()> {
+    let payload = base64::encode(&frame.rgb);
+    let count = payload.len().div_ceil(CHUNK_SIZE);
+    for (index, chunk) in payload.chunks(CHUNK_SIZE).enumerate() {
+        let more = u8::from(index + 1 < count);
+        if index == 0 {
+            write!(out, "\x1B_Ga=T,f=24,s={},v={},i={},p={},C=1,q=2,m={};", frame.width, frame.height, IMAGE_ID, PLACEMENT_ID, more)?;
+        }
+        else {
+            write!(out, "\x1B_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        out.write_all(b"\x1B\\")?;
+    }
+
+    Ok(())
+}
+
+/// Takes the image off the screen and frees its data in the terminal.
+pub fn delete<W: Write>(out: &mut W) -> io::Result<()> {
+    write!(out, "\x1B_Ga=d,d=I,i={},q=2\x1B\\", IMAGE_ID)
+}
@@ -0,0 +1,21 @@
+pub mod args;
+pub mod event_loop;
+pub mod media;
+pub mod audio;
+pub mod terminal;
+pub mod ascii;
+pub mod controller;
+pub mod backend;
+pub mod opencl;
+pub mod config;
+pub mod blocks;
+pub mod braille;
+pub mod glyph;
+pub mod edges;
+pub mod dither;
+pub mod adjust;
+pub mod palette;
+pub mod sixel;
+pub mod base64;
+pub mod kitty;
+pub mod iterm;
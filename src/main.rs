@@ -1,48 +1,88 @@
-mod args;
-mod event_loop;
-mod media;
-mod audio;
-mod terminal;
-mod ascii;
-mod controller;
+use std::process;
 
-use std::env;
-
-use audio::AudioController;
+use clap::Parser;
 use crossbeam::channel::unbounded;
-use event_loop::{EventLoopController, LoopEvent};
-use media::MediaController;
-use opencv::prelude::*;
-use terminal::{StringInfo, TerminalController};
-use crate::controller::Controller;
+use the::args::Args;
+use the::audio::AudioController;
+use the::config::Settings;
+use the::controller::Controller;
+use the::event_loop::{EventLoopController, LoopEvent};
+use the::media::MediaController;
+use the::opencl;
+use the::terminal::{self, Frame, TerminalController};
 
 fn main() {
-    println!("Hello, world!");
-    let (tx_frame, rx_frame) = unbounded::<StringInfo>();
-    let (txs_event, rxs_event): (Vec<_>, Vec<_>) = (0..3).map(|_| unbounded::<LoopEvent>()).unzip();
+    let args = Args::parse();
+    if args.list_devices {
+        print!("{}", opencl::list_devices());
+        return;
+    }
 
-    let args: Vec<String> = env::args().collect();
+    let mut settings = match Settings::new(args) {
+        Ok(settings) => settings,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(2);
+        },
+    };
+    settings.detect_terminal();
 
-    //let mut audio_controller = AudioController::new(&args[1], &rxs_event[0]);
-    let mut media_controller = MediaController::new(&String::from(r#"https://muxer.sf-converter.com/get/QlprOFVaN2E3dVl8VHdvIFRpbWUgfCBBbmltYXRpb24gTWVtZXwxNzI5NjA1NDYy.b6234ac485634fab3b534a2f6a10ace4"#), &tx_frame, &rxs_event[1]).unwrap();
-    let mut terminal_controller = TerminalController::new(&rx_frame, &rxs_event[2]);
-    let mut event_loop_controller = EventLoopController::new(&txs_event);
+    let (tx_frame, rx_frame) = unbounded::<Frame>();
+    let (txs_event, rxs_event): (Vec<_>, Vec<_>) = (0..4).map(|_| unbounded::<LoopEvent>()).unzip();
 
-    let _x = crossbeam::scope(|x| {
-        x.spawn(move |_| {
+    let mut media_controller = match MediaController::new(&settings, &tx_frame, &rxs_event[1], &txs_event) {
+        Ok(media_controller) => media_controller,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(1);
+        },
+    };
+    let mut event_loop_controller = match EventLoopController::new(&txs_event[..3], &rxs_event[3]) {
+        Ok(event_loop_controller) => event_loop_controller,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(1);
+        },
+    };
+    let synchronized = match terminal::enter() {
+        Ok(synchronized) => synchronized,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(1);
+        },
+    };
+    let mut terminal_controller = TerminalController::new(&settings, &rx_frame, &rxs_event[2], synchronized);
+    let audio_receiver = &rxs_event[0];
+    let settings = &settings;
+
+    let error = crossbeam::scope(|x| {
+        let media = x.spawn(move |_| {
             media_controller.run();
+            media_controller.take_error()
         });
         x.spawn(move |_| {
             terminal_controller.run();
         });
         x.spawn(move |_| {
-            //audio_controller.run();
+            if settings.mute {
+                return;
+            }
+
+            // Not every input has an audio track rodio can decode; play silently then.
+            if let Ok(mut audio_controller) = AudioController::new(&settings.input, settings.start, audio_receiver) {
+                audio_controller.run();
+            }
         });
         x.spawn(move |_| {
             event_loop_controller.run();
         });
-    });
-}
-
 
+        media.join().ok().flatten()
+    }).ok().flatten();
 
+    terminal::restore();
+    if let Some(error) = error {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
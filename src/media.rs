@@ -1,24 +1,20 @@
 use std::thread::sleep;
 use std::time::{Duration, SystemTime};
-use std::ptr;
-
-use opencl3::command_queue::{CommandQueue, CL_QUEUE_PROFILING_ENABLE};
-use opencl3::context::Context;
-use opencl3::device::{get_all_devices, Device, CL_DEVICE_GLOBAL_MEM_SIZE, CL_DEVICE_MAX_WORK_ITEM_SIZES, CL_DEVICE_TYPE_GPU};
-use opencl3::kernel::{ExecuteKernel, Kernel};
-use opencl3::memory::{Buffer, CL_MEM_READ_ONLY, CL_MEM_WRITE_ONLY};
-use opencl3::program::Program;
-use opencl3::types::{cl_bool, cl_char, cl_event, cl_float, cl_uchar, cl_uint, CL_BLOCKING, CL_NON_BLOCKING};
-
 
 use crossbeam::channel::{Sender, Receiver};
-use opencv::core::{MatTraitConst, MatTraitConstManual, Size};
-use opencv::videoio::{VideoCapture, VideoCaptureTraitConst, CAP_ANY, CAP_PROP_POS_FRAMES};
+use opencv::core::{MatTraitConst, Rect, Size};
+use opencv::videoio::{VideoCapture, VideoCaptureTraitConst, CAP_ANY, CAP_PROP_POS_FRAMES, CAP_PROP_POS_MSEC};
 use opencv::{imgcodecs, imgproc};
 use opencv::prelude::Mat;
 use opencv::prelude::VideoCaptureTrait;
-use crate::event_loop::{self, LoopEvent};
-use crate::{ascii::AsciiConverter, terminal::StringInfo};
+use crate::adjust::Adjuster;
+use crate::args::{ColorMode, Output, ScaleMode};
+use crate::backend::{self, ConversionBackend};
+use crate::config::Settings;
+use crate::dither::Ditherer;
+use crate::event_loop::LoopEvent;
+use crate::glyph;
+use crate::terminal::{Frame, PixelFrame};
 use crate::controller::Controller;
 
 pub enum MediaType {
@@ -28,96 +24,294 @@ pub enum MediaType {
 
 pub struct MediaController<'a> {
     event_loop_receiver: &'a Receiver<LoopEvent>,
-    media_sender: &'a Sender<StringInfo>,
-    ascii_converter: AsciiConverter,
+    // Every controller's events, to tell them when the media ends or starts over.
+    event_loop_senders: &'a [Sender<LoopEvent>],
+    media_sender: &'a Sender<Frame>,
     media_type: MediaType,
-
-    kernel: Kernel,
-    queue: CommandQueue,
-    context: Context,
+    pipeline: Pipeline,
+
+    fps: Option<f64>,
+    max_fps: Option<f64>,
+    start: f64,
+    looping: bool,
+    // Why playback stopped, when it did not simply end.
+    error: Option<String>,
 }
 
 impl<'a> MediaController<'a> {
-    pub fn new(uri: &String, media_sender: &'a Sender<StringInfo>, event_loop_receiver: &'a Receiver<LoopEvent>) -> Result<Self, String> {
-        let mut media_type: Option<MediaType> = None;
-        let result = imgcodecs::have_image_reader(uri);
-        if result.is_ok() && result.unwrap() {
-            //media_type = Some(MediaType::Image(imgcodecs::imread(uri, imgcodecs::IMREAD_GRAYSCALE).unwrap())); 
-                        media_type = Some(MediaType::Video(VideoCapture::from_file(uri, CAP_ANY).unwrap()));           
+    pub fn new(settings: &Settings, media_sender: &'a Sender<Frame>, event_loop_receiver: &'a Receiver<LoopEvent>, event_loop_senders: &'a [Sender<LoopEvent>]) -> Result<Self, String> {
+        let uri = &settings.input;
+        let media_type = match imgcodecs::have_image_reader(uri) {
+            Ok(true) => {
+                let image = imgcodecs::imread(uri, imgcodecs::IMREAD_COLOR).map_err(|error| error.message)?;
+                if image.empty() {
+                    open_video(uri)?
+                }
+                else {
+                    MediaType::Image(image)
+                }
+            },
+            _ => open_video(uri)?,
+        };
+
+        // Graphics are scaled in square pixels instead of cells.
+        let graphics = settings.output.is_graphics();
+        // The ramp of custom characters is measured in the font once for everything using it.
+        let chars = glyph::chars(settings)?;
+        let mut pipeline = Pipeline {
+            backend: backend::create(settings, &chars)?,
+            adjuster: Adjuster::new(settings),
+            ditherer: Ditherer::new(settings, &chars),
+            grayscale: settings.color_mode == ColorMode::Grayscale,
+            output: settings.output,
+            scaler: Scaler {
+                mode: settings.scale,
+                cell_aspect: if graphics { Some(1.0) } else { settings.cell_aspect },
+                detected_cell_aspect: CELL_ASPECT,
+                cell_size: if graphics { (1, 1) } else { settings.mode.cell_size() },
+            },
+            width: settings.width,
+            height: settings.height,
+            bounds: Size::default(),
+            cell_pixels: CELL_PIXELS,
+            resized: Mat::default(),
+            gray: Mat::default(),
+            rgb: Mat::default(),
+        };
+        pipeline.resize();
 
+        Ok(Self {
+            event_loop_receiver,
+            event_loop_senders,
+            media_sender,
+            media_type,
+            pipeline,
+            fps: settings.fps,
+            max_fps: settings.output.max_fps(),
+            start: settings.start,
+            looping: settings.looping,
+            error: None,
+        })
+    }
+
+    /// The error that stopped `run`, if any, to be shown once the terminal is restored.
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    /// Stops the other controllers, for when the media ends before the player is quit.
+    fn shutdown(&self) {
+        for sender in self.event_loop_senders {
+            let _ = sender.send(LoopEvent::Shutdown);
+        }
+    }
+}
+
+fn open_video(uri: &str) -> Result<MediaType, String> {
+    let video = VideoCapture::from_file(uri, CAP_ANY).map_err(|error| error.message)?;
+    if !video.is_opened().unwrap_or(false) {
+        return Err(format!("could not open {}", uri));
+    }
+
+    Ok(MediaType::Video(video))
+}
+
+// Terminal cells are about twice as tall as they are wide, and about this many pixels,
+// for terminals that do not report their size in pixels.
+const CELL_ASPECT: f64 = 2.0;
+const CELL_PIXELS: (f64, f64) = (8.0, 16.0);
+
+/// Width and height of a terminal cell in pixels, measured from the terminal's size in pixels.
+fn detect_cell_pixels() -> Option<(f64, f64)> {
+    match (termion::terminal_size(), termion::terminal_size_pixels()) {
+        (Ok((columns, rows)), Ok((width, height))) if columns > 0 && rows > 0 && width > 0 && height > 0 => {
+            Some((width as f64 / columns as f64, height as f64 / rows as f64))
+        },
+        _ => None,
+    }
+}
+
+/// The part of a frame to show and its size in cells within `bounds`. Fit shows all of
+/// the frame as large as it fits, fill covers the bounds and crops the overflow evenly
+/// from both sides and stretch fills the bounds with all of the frame.
+fn layout(frame: Size, bounds: Size, scale: ScaleMode, cell_aspect: f64) -> (Rect, Size) {
+    let whole = Rect::new(0, 0, frame.width, frame.height);
+    let width = frame.width as f64;
+    let height = frame.height as f64 / cell_aspect;
+    match scale {
+        ScaleMode::Stretch => (whole, bounds),
+        ScaleMode::Fit => {
+            let scale = (bounds.width as f64 / width).min(bounds.height as f64 / height);
+            (whole, Size::new(((width * scale).round() as i32).clamp(1, bounds.width), ((height * scale).round() as i32).clamp(1, bounds.height)))
+        },
+        ScaleMode::Fill => {
+            let scale = (bounds.width as f64 / width).max(bounds.height as f64 / height);
+            let crop_width = ((bounds.width as f64 / scale).round() as i32).clamp(1, frame.width);
+            let crop_height = ((bounds.height as f64 * cell_aspect / scale).round() as i32).clamp(1, frame.height);
+            (Rect::new((frame.width - crop_width) / 2, (frame.height - crop_height) / 2, crop_width, crop_height), bounds)
+        },
+    }
+}
+
+fn pixel_size(cells: Size, cell_size: (i32, i32)) -> Size {
+    Size::new(cells.width * cell_size.0, cells.height * cell_size.1)
+}
+
+// Some terminals, and pipes, report a size of 0x0.
+fn output_size(width: Option<u16>, height: Option<u16>) -> Size {
+    let terminal_size = termion::terminal_size().unwrap_or((80, 24));
+    Size::new(width.unwrap_or(terminal_size.0).max(1) as i32, height.unwrap_or(terminal_size.1).max(1) as i32)
+}
+
+struct Scaler {
+    mode: ScaleMode,
+    cell_aspect: Option<f64>,
+    // Measured from the terminal on every resize, used when `cell_aspect` is not configured.
+    detected_cell_aspect: f64,
+    cell_size: (i32, i32),
+}
+
+impl Scaler {
+    /// Crops and resizes `frame` into `resized`, to the pixels of the cells it is shown in
+    /// within `bounds`. `resized` is only reallocated when that size changes.
+    fn scale(&self, frame: &Mat, bounds: Size, interpolation: i32, resized: &mut Mat) -> opencv::Result<()> {
+        let cell_aspect = self.cell_aspect.unwrap_or(self.detected_cell_aspect);
+        let (crop, cells) = layout(frame.size()?, bounds, self.mode, cell_aspect);
+        imgproc::resize(&*Mat::roi(frame, crop)?, resized, pixel_size(cells, self.cell_size), 0.0, 0.0, interpolation)
+    }
+}
+
+/// Everything a decoded frame goes through on its way to the terminal, with the frames
+/// in between kept for the next one.
+struct Pipeline {
+    backend: Box<dyn ConversionBackend + Send>,
+    adjuster: Adjuster,
+    ditherer: Option<Ditherer>,
+    grayscale: bool,
+    output: Output,
+    scaler: Scaler,
+
+    width: Option<u16>,
+    height: Option<u16>,
+    // In cells for text and in pixels for graphics.
+    bounds: Size,
+    cell_pixels: (f64, f64),
+
+    resized: Mat,
+    gray: Mat,
+    rgb: Mat,
+}
+
+impl Pipeline {
+    /// Takes the output size and cell size from the terminal again, after it was resized.
+    fn resize(&mut self) {
+        let cell_pixels = detect_cell_pixels();
+        self.cell_pixels = cell_pixels.unwrap_or(CELL_PIXELS);
+        self.scaler.detected_cell_aspect = cell_pixels.map_or(CELL_ASPECT, |(width, height)| height / width);
+
+        self.bounds = output_size(self.width, self.height);
+        if self.output.is_graphics() {
+            // The last row stays free, the cursor goes below the picture after it and
+            // would scroll the screen there.
+            let rows = (self.bounds.height - 1).max(1);
+            self.bounds = Size::new(((self.bounds.width as f64 * self.cell_pixels.0) as i32).max(1), ((rows as f64 * self.cell_pixels.1) as i32).max(1));
+        }
+    }
+
+    fn render(&mut self, frame: &Mat, interpolation: i32) -> Result<Frame, String> {
+        self.scaler.scale(frame, self.bounds, interpolation, &mut self.resized).map_err(|error| error.message)?;
+        if self.resized.empty() {
+            return Err("empty frame".to_string());
+        }
+
+        let frame = if self.grayscale {
+            imgproc::cvt_color(&self.resized, &mut self.gray, imgproc::COLOR_BGR2GRAY, 0).map_err(|error| error.message)?;
+            &mut self.gray
         }
         else {
-            media_type = Some(MediaType::Video(VideoCapture::from_file(uri, CAP_ANY).unwrap()));           
+            &mut self.resized
+        };
+
+        self.adjuster.apply(frame)?;
+        if let Some(ditherer) = self.ditherer.as_mut() {
+            ditherer.apply(frame)?;
         }
 
-        if media_type.is_none() {
-            return Err("No supported file extensionfsdfsad".to_string());
+        if !self.output.is_graphics() {
+            return self.backend.convert(frame).map(Frame::Text);
         }
-        
-        let device_id = *get_all_devices(CL_DEVICE_TYPE_GPU).unwrap().first().expect("no device found in platform");
-        let device = Device::new(device_id);
-        let context = Context::from_device(&device).expect("context::from_device failed");
-        let queue = CommandQueue::create_default_with_properties(&context, CL_QUEUE_PROFILING_ENABLE, 200).expect("commandqueue::create_default failed");
-        let program = Program::create_and_build_from_source(&context, crate::ascii::PROGMRAM, "").expect("program::create_and_build_from_source failed");
-        let kernel = Kernel::create(&program, "calculate").unwrap();
 
-        Ok(Self {
-            ascii_converter: AsciiConverter::new(&crate::ascii::CHARS3.to_string()),
-            event_loop_receiver,
-            media_sender,
-            media_type: media_type.unwrap(),
-            kernel,
-            queue,
-            context,
-        })
+        let code = if frame.channels() == 1 { imgproc::COLOR_GRAY2RGB } else { imgproc::COLOR_BGR2RGB };
+        imgproc::cvt_color(&*frame, &mut self.rgb, code, 0).map_err(|error| error.message)?;
+        Ok(Frame::Pixels(PixelFrame {
+            width: self.rgb.cols() as u32,
+            height: self.rgb.rows() as u32,
+            columns: (self.rgb.cols() as f64 / self.cell_pixels.0).ceil() as u16,
+            rows: (self.rgb.rows() as f64 / self.cell_pixels.1).ceil() as u16,
+            rgb: self.rgb.data_bytes().map_err(|error| error.message)?.to_vec(),
+        }))
     }
 }
 
 impl<'a> Controller for MediaController<'a> {
     fn run(&mut self) {
         match &mut self.media_type {
-            MediaType::Image(x) => {
-                let terminal_size = termion::terminal_size().unwrap();
-                let size = Size::new(terminal_size.0 as i32, terminal_size.1 as i32);
-                let mut mat = Mat::default();
-                let img = imgproc::resize(x, &mut mat, size, 0.0, 0.0, imgproc::INTER_LINEAR);
-                self.media_sender.send(self.ascii_converter.convert(&mat, true)).unwrap(); 
-            },
-            MediaType::Video(video) => {
-                let fps = video.get(opencv::videoio::CAP_PROP_FPS).unwrap_or(30.0);
-                let ms_per_frame = (1000.0f64 / fps).floor() as u64;
-                let mut frame_index = 0i64;
+            MediaType::Image(image) => {
+                let mut redraw = true;
+                loop {
+                    if redraw {
+                        redraw = false;
+                        match self.pipeline.render(image, imgproc::INTER_AREA) {
+                            Ok(rendered) => self.media_sender.send(rendered).unwrap(),
+                            Err(error) => {
+                                self.error = Some(error);
+                                break;
+                            },
+                        }
+                    }
 
-                let chars = crate::ascii::NO.chars().collect::<Vec<char>>();
-                let char_len = chars.iter().map(|x| x.len_utf8()).max().unwrap() as u32;
-                let mut char_bytes = Vec::<u8>::new();
-                for char in &chars {
-                    let mut bytes = vec![0; char_len as usize];
-                    char.encode_utf8(&mut bytes);
-                    for byte in bytes {
-                        char_bytes.push(byte);
+                    // A still image has nothing to play, so it is only drawn again when the
+                    // terminal is resized or the picture adjusted.
+                    match self.event_loop_receiver.recv() {
+                        Ok(LoopEvent::Shutdown) | Err(_) => break,
+                        Ok(LoopEvent::Resize) => {
+                            self.pipeline.resize();
+                            redraw = true;
+                        },
+                        Ok(LoopEvent::Adjust(adjustment)) => {
+                            self.pipeline.adjuster.adjust(adjustment);
+                            redraw = true;
+                        },
+                        _ => {},
                     }
                 }
 
-                println!("{}", char_len);
-                println!("{:?}", char_bytes);
-                println!("{}", String::from_utf8(char_bytes.clone()).unwrap());
-                
-                let mut size = 0;
-
-
-
-                let mut chars_buffer = unsafe { Buffer::<cl_uchar>::create(&self.context, CL_MEM_READ_ONLY, chars.len() * char_len as usize, ptr::null_mut()).unwrap() };
-                let mut output_buffer = unsafe { Buffer::<cl_uchar>::create(&self.context, CL_MEM_WRITE_ONLY, 1, ptr::null_mut()).unwrap() };
-                let mut frame_buffer = unsafe { Buffer::<cl_uchar>::create(&self.context, CL_MEM_READ_ONLY, 1, ptr::null_mut()).unwrap() };
+                if self.error.is_some() {
+                    self.shutdown();
+                }
+            },
+            MediaType::Video(video) => {
+                let source_fps = video.get(opencv::videoio::CAP_PROP_FPS).ok().filter(|x| *x > 0.0).unwrap_or(30.0);
+                let fps = self.fps.unwrap_or(source_fps);
+                // Outputs with heavy frames show only some of them, skipping the others in
+                // between so that the video keeps its speed.
+                let shown_fps = match (self.fps, self.max_fps) {
+                    (None, Some(max_fps)) => fps.min(max_fps),
+                    _ => fps,
+                };
+                let stride = fps / shown_fps;
+                let mut stride_debt = 0.0;
+                let ms_per_frame = (1000.0f64 / shown_fps).floor() as u64;
+                let mut frame_index = 0i64;
 
-                let chars_buffer_write_event = unsafe { self.queue.enqueue_write_buffer(&mut chars_buffer, CL_NON_BLOCKING, 0, &char_bytes, &[]) };
+                if self.start > 0.0 {
+                    let _ = video.set(CAP_PROP_POS_MSEC, self.start * 1000.0);
+                    frame_index = (self.start * source_fps) as i64;
+                }
 
-                let grayscale: cl_uint = 0;
-                let step: cl_uint = (255.0 / (chars.len() as f32)).ceil() as u32;
                 let mut is_playing = true;
                 let mut shutdown = false;
-                let now = SystemTime::now();
+                let mut frame = Mat::default();
                 loop {
                     if shutdown {
                         break;
@@ -125,12 +319,12 @@ impl<'a> Controller for MediaController<'a> {
 
                     if !self.event_loop_receiver.is_empty() || !is_playing {
                         let event = self.event_loop_receiver.recv().unwrap();
-                        print!("{:?}", event);
+                        let mut redraw = false;
                         match event {
                             LoopEvent::Shutdown => { shutdown = true; },
                             LoopEvent::PlayPause => { is_playing = !is_playing; },
                             LoopEvent::Skip(x) => {
-                                let frame_to_skip = x as i64 * fps as i64;
+                                let frame_to_skip = x as i64 * source_fps as i64;
                                 if x < 0 && (frame_to_skip + frame_index) < 0 {
                                     frame_index = 0; 
                                 }
@@ -139,6 +333,27 @@ impl<'a> Controller for MediaController<'a> {
                                 }
                                 video.set(CAP_PROP_POS_FRAMES, frame_index as f64).unwrap();
                             },
+                            LoopEvent::Resize => {
+                                self.pipeline.resize();
+                                redraw = true;
+                            },
+                            LoopEvent::Adjust(adjustment) => {
+                                self.pipeline.adjuster.adjust(adjustment);
+                                redraw = true;
+                            },
+                            LoopEvent::Restart => {},
+                        }
+
+                        // A paused video shows its last frame again at the new size or with the
+                        // new adjustments, a playing one picks them up with the next frame.
+                        if redraw && !is_playing && !frame.empty() {
+                            match self.pipeline.render(&frame, imgproc::INTER_LINEAR) {
+                                Ok(rendered) => self.media_sender.send(rendered).unwrap(),
+                                Err(error) => {
+                                    self.error = Some(error);
+                                    break;
+                                },
+                            }
                         }
 
                         continue;
@@ -146,67 +361,45 @@ impl<'a> Controller for MediaController<'a> {
                     
                     let start_time = SystemTime::now();
 
-                    let mut frame = Mat::default();
-                    let result = video.read(&mut frame);
-                    if result.is_err() || !result.unwrap() || frame.empty() {
-                        break;
-                    }
-
-                    let terminal_size = termion::terminal_size().unwrap();
-                    let new_size = Size::new(terminal_size.0 as i32, terminal_size.1 as i32);
-                    
-                    let mut resized_frame = Mat::default();
-                    let result = imgproc::resize(&frame, &mut resized_frame, new_size, 0.0, 0.0, imgproc::INTER_LINEAR);
-                    if result.is_err() || resized_frame.empty() {
-                        break;
-                    }
-
-                    if grayscale == 1 {
-                        let mut gray_frame = Mat::default();
-                        let result = imgproc::cvt_color(&resized_frame, &mut gray_frame, imgproc::COLOR_BGR2GRAY, 0);
-                        if result.is_err() || gray_frame.empty() {
+                    let read = match video.read(&mut frame) {
+                        Ok(read) => read,
+                        Err(error) => {
+                            self.error = Some(error.message);
                             break;
+                        },
+                    };
+                    if !read || frame.empty() {
+                        if self.looping && frame_index > 0 {
+                            frame_index = 0;
+                            if video.set(CAP_PROP_POS_FRAMES, 0.0).unwrap_or(false) {
+                                for sender in self.event_loop_senders {
+                                    let _ = sender.send(LoopEvent::Restart);
+                                }
+                                continue;
+                            }
                         }
 
-                        resized_frame = gray_frame; 
+                        break;
                     }
 
-                    let frame_bytes = resized_frame.data_bytes().unwrap();
-                    if size != frame_bytes.len() {
-                        size = frame_bytes.len();
-                        frame_buffer = unsafe { Buffer::<cl_uchar>::create(&self.context, CL_MEM_READ_ONLY, size, ptr::null_mut()).unwrap() };
-                        output_buffer = unsafe { Buffer::<cl_uchar>::create(&self.context, CL_MEM_WRITE_ONLY, size * char_len as usize / 3, ptr::null_mut()).unwrap() };
-                    }
-                    
-                    let _ = unsafe { self.queue.enqueue_write_buffer(&mut frame_buffer, CL_BLOCKING, 0, resized_frame.data_bytes().unwrap(), &[]) };
-
-                    let execute = unsafe {
-                        ExecuteKernel::new(&self.kernel)
-                            .set_arg(&frame_buffer)
-                            .set_arg(&chars_buffer)
-                            .set_arg(&char_len)
-                            .set_arg(&grayscale)
-                            .set_arg(&step)
-                            .set_arg(&output_buffer)
-                            .set_event_wait_list(&[chars_buffer_write_event.as_ref().unwrap().get()])
-                            .set_global_work_size(size / 3)
-                            .enqueue_nd_range(&self.queue).unwrap()
+                    let rendered = match self.pipeline.render(&frame, imgproc::INTER_LINEAR) {
+                        Ok(rendered) => rendered,
+                        Err(error) => {
+                            self.error = Some(error);
+                            break;
+                        },
                     };
+                    
+                    self.media_sender.send(rendered).unwrap(); 
 
-                    self.queue.flush().unwrap();
-                    self.queue.finish().unwrap();
-
-                    let mut string: Vec<cl_uchar> = vec![0; size * char_len as usize / 3];
-                    let _ = unsafe { self.queue.enqueue_read_buffer(&output_buffer, CL_BLOCKING, 0, &mut string, &[execute.get()]).unwrap() };
-
-                    let mut rgb = Vec::new();
-                    if grayscale == 0 {
-                        rgb = frame_bytes.to_vec();
+                    stride_debt += stride - 1.0;
+                    while stride_debt >= 1.0 {
+                        stride_debt -= 1.0;
+                        frame_index += 1;
+                        if !video.grab().unwrap_or(false) {
+                            break;
+                        }
                     }
-                    
-                    self.media_sender.send(StringInfo {string, rgb, char_len}).unwrap(); 
-                    self.queue.flush().unwrap();
-                    self.queue.finish().unwrap();
 
                     let time = start_time.elapsed();
                     if time.is_err() {
@@ -221,7 +414,7 @@ impl<'a> Controller for MediaController<'a> {
                         continue;
                     }
 
-                    let frames_to_skip = (time - deadtime_to_frame_preparing).div_duration_f64(Duration::from_millis(ms_per_frame)).ceil() as u64;
+                    let frames_to_skip = ((time - deadtime_to_frame_preparing).div_duration_f64(Duration::from_millis(ms_per_frame)) * stride).ceil() as u64;
                     frame_index += frames_to_skip as i64;
 
                     {
@@ -234,10 +427,58 @@ impl<'a> Controller for MediaController<'a> {
                     }
                 }
 
-                println!("{}", termion::clear::All);
-                println!("{:?}", now.elapsed().unwrap().as_secs());
+                if !shutdown {
+                    self.shutdown();
+                }
             },
 
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_letterboxes_wide_frames() {
+        // 200x100 pixels are 200x50 cells, which fit into 80x24 as 80x20.
+        let (crop, cells) = layout(Size::new(200, 100), Size::new(80, 24), ScaleMode::Fit, 2.0);
+        assert_eq!(crop, Rect::new(0, 0, 200, 100));
+        assert_eq!(cells, Size::new(80, 20));
+    }
+
+    #[test]
+    fn fit_pillarboxes_tall_frames() {
+        let (crop, cells) = layout(Size::new(100, 200), Size::new(80, 24), ScaleMode::Fit, 2.0);
+        assert_eq!(crop, Rect::new(0, 0, 100, 200));
+        assert_eq!(cells, Size::new(24, 24));
+    }
+
+    #[test]
+    fn fill_crops_evenly_from_both_sides() {
+        // Filling 24 rows scales 200x50 cells to 96x24, so 80 of the 96 columns are shown.
+        let (crop, cells) = layout(Size::new(200, 100), Size::new(80, 24), ScaleMode::Fill, 2.0);
+        assert_eq!(crop, Rect::new(16, 0, 167, 100));
+        assert_eq!(cells, Size::new(80, 24));
+
+        let (crop, _) = layout(Size::new(100, 200), Size::new(80, 24), ScaleMode::Fill, 2.0);
+        assert_eq!(crop, Rect::new(0, 70, 100, 60));
+    }
+
+    #[test]
+    fn stretch_fills_the_bounds_with_the_whole_frame() {
+        let (crop, cells) = layout(Size::new(200, 100), Size::new(80, 24), ScaleMode::Stretch, 2.0);
+        assert_eq!(crop, Rect::new(0, 0, 200, 100));
+        assert_eq!(cells, Size::new(80, 24));
+    }
+
+    #[test]
+    fn tiny_bounds_keep_a_cell() {
+        for scale in [ScaleMode::Fit, ScaleMode::Fill, ScaleMode::Stretch] {
+            let (crop, cells) = layout(Size::new(1920, 1080), Size::new(1, 1), scale, 2.0);
+            assert_eq!(cells, Size::new(1, 1));
+            assert!(crop.width >= 1 && crop.height >= 1);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::ptr;
+
+use opencl3::command_queue::{CommandQueue, CL_QUEUE_PROFILING_ENABLE};
+use opencl3::context::Context;
+use opencl3::device::{Device, CL_DEVICE_TYPE_ACCELERATOR, CL_DEVICE_TYPE_ALL, CL_DEVICE_TYPE_CPU, CL_DEVICE_TYPE_GPU};
+use opencl3::event::Event;
+use opencl3::kernel::{ExecuteKernel, Kernel};
+use opencl3::memory::{Buffer, CL_MEM_READ_ONLY, CL_MEM_WRITE_ONLY};
+use opencl3::platform::get_platforms;
+use opencl3::program::Program;
+use opencl3::types::{cl_device_type, cl_uchar, cl_uint, CL_BLOCKING};
+use opencv::core::{MatTraitConst, MatTraitConstManual};
+use opencv::prelude::Mat;
+
+use crate::args::{DotMode, RenderMode};
+use crate::ascii;
+use crate::backend::ConversionBackend;
+use crate::config::Settings;
+use crate::terminal::StringInfo;
+
+pub struct OpenClBackend {
+    kernel: Kernel,
+    braille_kernel: Kernel,
+    edges_kernel: Kernel,
+    queue: CommandQueue,
+    context: Context,
+
+    chars_buffer: Buffer<cl_uchar>,
+    frame_buffer: Option<Buffer<cl_uchar>>,
+    output_buffer: Option<Buffer<cl_uchar>>,
+    color_buffer: Option<Buffer<cl_uchar>>,
+    frame_size: usize,
+
+    mode: RenderMode,
+    char_len: cl_uint,
+    step: cl_uint,
+    threshold: cl_uint,
+    ordered: cl_uint,
+    edge_threshold: cl_uint,
+}
+
+pub struct DeviceInfo {
+    pub device: Device,
+    pub name: String,
+    pub platform: String,
+    pub device_type: cl_device_type,
+}
+
+impl DeviceInfo {
+    fn type_name(&self) -> &'static str {
+        if self.device_type & CL_DEVICE_TYPE_GPU != 0 {
+            "GPU"
+        }
+        else if self.device_type & CL_DEVICE_TYPE_CPU != 0 {
+            "CPU"
+        }
+        else if self.device_type & CL_DEVICE_TYPE_ACCELERATOR != 0 {
+            "accelerator"
+        }
+        else {
+            "other"
+        }
+    }
+}
+
+/// Every OpenCL device of every platform, in platform order. Machines without an
+/// OpenCL runtime simply have no devices.
+pub fn devices() -> Vec<DeviceInfo> {
+    let mut devices = Vec::new();
+    for platform in get_platforms().unwrap_or_default() {
+        let platform_name = platform.name().unwrap_or_default();
+        for device_id in platform.get_devices(CL_DEVICE_TYPE_ALL).unwrap_or_default() {
+            let device = Device::new(device_id);
+            devices.push(DeviceInfo {
+                name: device.name().unwrap_or_default(),
+                platform: platform_name.clone(),
+                device_type: device.dev_type().unwrap_or(0),
+                device,
+            });
+        }
+    }
+
+    devices
+}
+
+pub fn list_devices() -> String {
+    let devices = devices();
+    if devices.is_empty() {
+        return "no OpenCL devices found\n".to_string();
+    }
+
+    devices.iter().enumerate()
+        .map(|(index, device)| format!("{}: {} ({}, {})\n", index, device.name, device.type_name(), device.platform))
+        .collect()
+}
+
+/// Picks a device by its index in `devices()` or by a case-insensitive part of its name.
+/// Without a selector GPUs are preferred over accelerators and those over anything else,
+/// such as CPU runtimes like PoCL.
+fn select_device(selector: Option<&str>) -> Result<Device, String> {
+    let mut devices = devices();
+    if devices.is_empty() {
+        return Err("no OpenCL device found".to_string());
+    }
+
+    let index = match selector {
+        Some(selector) => match selector.parse::<usize>() {
+            Ok(index) if index < devices.len() => index,
+            Ok(index) => return Err(format!("no OpenCL device with index {}, see --list-devices", index)),
+            Err(_) => {
+                let selector = selector.to_lowercase();
+                devices.iter().position(|device| device.name.to_lowercase().contains(&selector))
+                    .ok_or(format!("no OpenCL device matching {}, see --list-devices", selector))?
+            },
+        },
+        None => [CL_DEVICE_TYPE_GPU, CL_DEVICE_TYPE_ACCELERATOR].iter()
+            .find_map(|device_type| devices.iter().position(|device| device.device_type & device_type != 0))
+            .unwrap_or(0),
+    };
+
+    Ok(devices.swap_remove(index).device)
+}
+
+impl OpenClBackend {
+    pub fn new(settings: &Settings, chars: &str) -> Result<Self, String> {
+        let device = select_device(settings.device.as_deref())?;
+        let context = Context::from_device(&device).map_err(|error| format!("context::from_device failed: {}", error))?;
+        let queue = CommandQueue::create_default_with_properties(&context, CL_QUEUE_PROFILING_ENABLE, 200).map_err(|error| format!("commandqueue::create_default failed: {}", error))?;
+        let program = Program::create_and_build_from_source(&context, ascii::PROGMRAM, "").map_err(|error| format!("program::create_and_build_from_source failed: {}", error))?;
+        let kernel = Kernel::create(&program, "calculate").map_err(|error| error.to_string())?;
+        let braille_kernel = Kernel::create(&program, "braille").map_err(|error| error.to_string())?;
+        let edges_kernel = Kernel::create(&program, "edges").map_err(|error| error.to_string())?;
+
+        let (char_bytes, char_len) = ascii::encode_chars(chars);
+        let mut chars_buffer = unsafe { Buffer::<cl_uchar>::create(&context, CL_MEM_READ_ONLY, char_bytes.len(), ptr::null_mut()).map_err(|error| error.to_string())? };
+        unsafe { queue.enqueue_write_buffer(&mut chars_buffer, CL_BLOCKING, 0, &char_bytes, &[]).map_err(|error| error.to_string())? };
+
+        Ok(Self {
+            kernel,
+            braille_kernel,
+            edges_kernel,
+            queue,
+            context,
+            chars_buffer,
+            frame_buffer: None,
+            output_buffer: None,
+            color_buffer: None,
+            frame_size: 0,
+            mode: settings.mode,
+            char_len,
+            step: ascii::step(chars.chars().count()),
+            threshold: settings.threshold as cl_uint,
+            ordered: (settings.dots == DotMode::Ordered) as cl_uint,
+            edge_threshold: settings.edge_threshold as cl_uint,
+        })
+    }
+
+    fn prepare_buffers(&mut self, frame_bytes: &[u8], cells: usize, cell_len: usize) -> Result<Event, String> {
+        if self.frame_size != frame_bytes.len() || self.frame_buffer.is_none() {
+            self.frame_size = frame_bytes.len();
+            self.frame_buffer = Some(unsafe { Buffer::<cl_uchar>::create(&self.context, CL_MEM_READ_ONLY, self.frame_size, ptr::null_mut()).map_err(|error| error.to_string())? });
+            self.output_buffer = Some(unsafe { Buffer::<cl_uchar>::create(&self.context, CL_MEM_WRITE_ONLY, cells * cell_len, ptr::null_mut()).map_err(|error| error.to_string())? });
+            self.color_buffer = Some(unsafe { Buffer::<cl_uchar>::create(&self.context, CL_MEM_WRITE_ONLY, cells * 3, ptr::null_mut()).map_err(|error| error.to_string())? });
+        }
+
+        unsafe { self.queue.enqueue_write_buffer(self.frame_buffer.as_mut().unwrap(), CL_BLOCKING, 0, frame_bytes, &[]).map_err(|error| error.to_string()) }
+    }
+
+    fn ramp(&mut self, frame: &Mat) -> Result<StringInfo, String> {
+        let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+        let pixels = (frame.rows() * frame.cols()) as usize;
+        let grayscale = (frame.channels() == 1) as cl_uint;
+        let write_event = self.prepare_buffers(frame_bytes, pixels, self.char_len as usize)?;
+        let output_buffer = self.output_buffer.as_ref().unwrap();
+
+        let execute = unsafe {
+            ExecuteKernel::new(&self.kernel)
+                .set_arg(self.frame_buffer.as_ref().unwrap())
+                .set_arg(&self.chars_buffer)
+                .set_arg(&self.char_len)
+                .set_arg(&grayscale)
+                .set_arg(&self.step)
+                .set_arg(output_buffer)
+                .set_event_wait_list(&[write_event.get()])
+                .set_global_work_size(pixels)
+                .enqueue_nd_range(&self.queue).map_err(|error| error.to_string())?
+        };
+
+        let mut string: Vec<cl_uchar> = vec![0; pixels * self.char_len as usize];
+        unsafe { self.queue.enqueue_read_buffer(output_buffer, CL_BLOCKING, 0, &mut string, &[execute.get()]).map_err(|error| error.to_string())? };
+
+        let mut rgb = Vec::new();
+        if grayscale == 0 {
+            rgb = ascii::rgb_colors(frame_bytes);
+        }
+
+        Ok(StringInfo {
+            string,
+            rgb,
+            char_len: self.char_len,
+            width: frame.cols() as u32,
+            background: Vec::new(),
+        })
+    }
+
+    fn edges(&mut self, frame: &Mat) -> Result<StringInfo, String> {
+        let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+        let width = frame.cols() as cl_uint;
+        let height = frame.rows() as cl_uint;
+        let pixels = (width * height) as usize;
+        let grayscale = (frame.channels() == 1) as cl_uint;
+        let write_event = self.prepare_buffers(frame_bytes, pixels, self.char_len as usize)?;
+        let output_buffer = self.output_buffer.as_ref().unwrap();
+
+        let execute = unsafe {
+            ExecuteKernel::new(&self.edges_kernel)
+                .set_arg(self.frame_buffer.as_ref().unwrap())
+                .set_arg(&self.chars_buffer)
+                .set_arg(&self.char_len)
+                .set_arg(&grayscale)
+                .set_arg(&self.step)
+                .set_arg(&width)
+                .set_arg(&height)
+                .set_arg(&self.edge_threshold)
+                .set_arg(output_buffer)
+                .set_event_wait_list(&[write_event.get()])
+                .set_global_work_size(pixels)
+                .enqueue_nd_range(&self.queue).map_err(|error| error.to_string())?
+        };
+
+        let mut string: Vec<cl_uchar> = vec![0; pixels * self.char_len as usize];
+        unsafe { self.queue.enqueue_read_buffer(output_buffer, CL_BLOCKING, 0, &mut string, &[execute.get()]).map_err(|error| error.to_string())? };
+
+        let mut rgb = Vec::new();
+        if grayscale == 0 {
+            rgb = ascii::rgb_colors(frame_bytes);
+        }
+
+        Ok(StringInfo {
+            string,
+            rgb,
+            char_len: self.char_len,
+            width,
+            background: Vec::new(),
+        })
+    }
+
+    fn braille(&mut self, frame: &Mat) -> Result<StringInfo, String> {
+        let frame_bytes = frame.data_bytes().map_err(|error| error.message)?;
+        let width = frame.cols() as cl_uint;
+        let channels = frame.channels() as cl_uint;
+        let cells = (frame.cols() / 2 * (frame.rows() / 4)) as usize;
+        let write_event = self.prepare_buffers(frame_bytes, cells, 3)?;
+        let output_buffer = self.output_buffer.as_ref().unwrap();
+        let color_buffer = self.color_buffer.as_ref().unwrap();
+
+        let execute = unsafe {
+            ExecuteKernel::new(&self.braille_kernel)
+                .set_arg(self.frame_buffer.as_ref().unwrap())
+                .set_arg(&width)
+                .set_arg(&channels)
+                .set_arg(&self.threshold)
+                .set_arg(&self.ordered)
+                .set_arg(output_buffer)
+                .set_arg(color_buffer)
+                .set_event_wait_list(&[write_event.get()])
+                .set_global_work_size(cells)
+                .enqueue_nd_range(&self.queue).map_err(|error| error.to_string())?
+        };
+
+        let mut string: Vec<cl_uchar> = vec![0; cells * 3];
+        unsafe { self.queue.enqueue_read_buffer(output_buffer, CL_BLOCKING, 0, &mut string, &[execute.get()]).map_err(|error| error.to_string())? };
+
+        let mut rgb = Vec::new();
+        if channels == 3 {
+            rgb = vec![0; cells * 3];
+            unsafe { self.queue.enqueue_read_buffer(color_buffer, CL_BLOCKING, 0, &mut rgb, &[execute.get()]).map_err(|error| error.to_string())? };
+        }
+
+        Ok(StringInfo {
+            string,
+            rgb,
+            char_len: 3,
+            width: (frame.cols() / 2) as u32,
+            background: Vec::new(),
+        })
+    }
+}
+
+impl ConversionBackend for OpenClBackend {
+    fn convert(&mut self, frame: &Mat) -> Result<StringInfo, String> {
+        match self.mode {
+            RenderMode::Braille => self.braille(frame),
+            RenderMode::Edge => self.edges(frame),
+            _ => self.ramp(frame),
+        }
+    }
+}
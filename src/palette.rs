@@ -0,0 +1,250 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+use crate::args::ColorDepth;
+
+// xterm's default values of the 16 ANSI colors.
+const ANSI_16: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], [0xCD, 0x00, 0x00], [0x00, 0xCD, 0x00], [0xCD, 0xCD, 0x00],
+    [0x00, 0x00, 0xEE], [0xCD, 0x00, 0xCD], [0x00, 0xCD, 0xCD], [0xE5, 0xE5, 0xE5],
+    [0x7F, 0x7F, 0x7F], [0xFF, 0x00, 0x00], [0x00, 0xFF, 0x00], [0xFF, 0xFF, 0x00],
+    [0x5C, 0x5C, 0xFF], [0xFF, 0x00, 0xFF], [0x00, 0xFF, 0xFF], [0xFF, 0xFF, 0xFF],
+];
+
+// Levels of every channel in the 6x6x6 color cube of the 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Picks the color depth from the environment: COLORTERM for truecolor, then the
+/// number of colors in the terminfo entry of TERM, then the name in TERM itself.
+pub fn detect() -> ColorDepth {
+    if env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit") {
+        return ColorDepth::Truecolor;
+    }
+
+    let term = env::var("TERM").unwrap_or_default();
+    match terminfo_colors(&term) {
+        Some(colors) if colors >= 1 << 24 => ColorDepth::Truecolor,
+        Some(colors) if colors >= 256 => ColorDepth::Ansi256,
+        Some(_) => ColorDepth::Ansi16,
+        None if term.contains("256color") => ColorDepth::Ansi256,
+        None => ColorDepth::Ansi16,
+    }
+}
+
+/// The `colors` number of the compiled terminfo entry for `term`, in the places ncurses
+/// looks for them.
+fn terminfo_colors(term: &str) -> Option<u32> {
+    let first = term.chars().next()?;
+    let mut directories = Vec::new();
+    if let Some(path) = env::var_os("TERMINFO") {
+        directories.push(PathBuf::from(path));
+    }
+    if let Some(home) = env::var_os("HOME") {
+        directories.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Ok(paths) = env::var("TERMINFO_DIRS") {
+        directories.extend(paths.split(':').filter(|path| !path.is_empty()).map(PathBuf::from));
+    }
+    directories.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(PathBuf::from));
+
+    let entry = directories.iter()
+        .flat_map(|directory| [directory.join(first.to_string()).join(term), directory.join(format!("{:x}", first as u32)).join(term)])
+        .find_map(|path| fs::read(path).ok())?;
+
+    // A header of six little-endian shorts: the magic number, which tells whether numbers
+    // are 16 or 32 bits, and the sizes of the names, booleans, numbers and strings. The
+    // numbers start on an even offset after the names and booleans.
+    let short = |offset: usize| entry.get(offset..offset + 2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]));
+    let number_size = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    let names_size = short(2)? as usize;
+    let booleans = short(4)? as usize;
+    let numbers = short(6)? as usize;
+
+    // `colors` is the number at index 13.
+    let index = 13;
+    if index >= numbers {
+        return None;
+    }
+    let offset = (12 + names_size + booleans).next_multiple_of(2) + index * number_size;
+    let colors = match number_size {
+        2 => short(offset)? as i32,
+        _ => i32::from_le_bytes(entry.get(offset..offset + 4)?.try_into().ok()?),
+    };
+
+    u32::try_from(colors).ok()
+}
+
+/// RGB value of every color a palette can pick from, by palette index. The 256-color
+/// palette leaves out the first 16 colors, which terminal themes tend to change.
+fn colors(depth: ColorDepth) -> Vec<(u8, [u8; 3])> {
+    match depth {
+        ColorDepth::Ansi16 => ANSI_16.iter().enumerate().map(|(index, &color)| (index as u8, color)).collect(),
+        _ => {
+            let cube = (0..216).map(|index| (16 + index as u8, [CUBE_LEVELS[index / 36], CUBE_LEVELS[index / 6 % 6], CUBE_LEVELS[index % 6]]));
+            let grays = (0..24).map(|index| (232 + index as u8, [8 + 10 * index as u8; 3]));
+            cube.chain(grays).collect()
+        },
+    }
+}
+
+/// The perceptually closest color of a palette, by distance in CIELAB, for every 24-bit
+/// color, searched for when a color first comes up and remembered from then on.
+struct Lookup {
+    colors: Vec<(u8, [f32; 3])>,
+    // Position in `colors` plus one, zero for colors not searched for yet.
+    nearest: Vec<AtomicU8>,
+}
+
+impl Lookup {
+    fn new(depth: ColorDepth) -> Self {
+        Self {
+            colors: colors(depth).into_iter().map(|(index, color)| (index, lab(color))).collect(),
+            nearest: (0..1 << 24).map(|_| AtomicU8::new(0)).collect(),
+        }
+    }
+
+    fn index(&self, color: [u8; 3]) -> u8 {
+        let key = (color[0] as usize) << 16 | (color[1] as usize) << 8 | color[2] as usize;
+        let position = match self.nearest[key].load(Ordering::Relaxed) {
+            0 => {
+                let lab = lab(color);
+                let position = (0..self.colors.len())
+                    .min_by(|&a, &b| distance(&self.colors[a].1, &lab).total_cmp(&distance(&self.colors[b].1, &lab)))
+                    .unwrap_or(0);
+                self.nearest[key].store(position as u8 + 1, Ordering::Relaxed);
+                position
+            },
+            entry => entry as usize - 1,
+        };
+
+        self.colors[position].0
+    }
+}
+
+fn lookup(depth: ColorDepth) -> &'static Lookup {
+    static ANSI_256_LOOKUP: OnceLock<Lookup> = OnceLock::new();
+    static ANSI_16_LOOKUP: OnceLock<Lookup> = OnceLock::new();
+    match depth {
+        ColorDepth::Ansi16 => ANSI_16_LOOKUP.get_or_init(|| Lookup::new(depth)),
+        _ => ANSI_256_LOOKUP.get_or_init(|| Lookup::new(depth)),
+    }
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// CIELAB coordinates of an sRGB color under D65.
+fn lab(color: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = color.map(|channel| {
+        let value = channel as f32 / 255.0;
+        if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+    });
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let [x, y, z] = [x, y, z].map(|value| if value > 0.008856 { value.cbrt() } else { 7.787 * value + 16.0 / 116.0 });
+
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+/// What `color` is drawn as at `depth`: the color packed as 0xRRGGBB for truecolor and
+/// the palette index otherwise. Equal codes need no new escape sequence.
+pub fn code(depth: ColorDepth, color: [u8; 3]) -> u32 {
+    match depth {
+        ColorDepth::Auto | ColorDepth::Truecolor => (color[0] as u32) << 16 | (color[1] as u32) << 8 | color[2] as u32,
+        _ => lookup(depth).index(color) as u32,
+    }
+}
+
+/// The color of the palette closest to `color`, or `color` itself for truecolor.
+pub fn nearest(depth: ColorDepth, color: [u8; 3]) -> [u8; 3] {
+    self::color(depth, code(depth, color))
+}
+
+/// RGB value of a `code` at `depth`.
+pub fn color(depth: ColorDepth, code: u32) -> [u8; 3] {
+    match depth {
+        ColorDepth::Auto | ColorDepth::Truecolor => [(code >> 16) as u8, (code >> 8) as u8, code as u8],
+        ColorDepth::Ansi16 => ANSI_16[code as usize % 16],
+        ColorDepth::Ansi256 => match code as usize {
+            index @ 0..=15 => ANSI_16[index],
+            index @ 16..=231 => {
+                let index = index - 16;
+                [CUBE_LEVELS[index / 36], CUBE_LEVELS[index / 6 % 6], CUBE_LEVELS[index % 6]]
+            },
+            index => [8 + 10 * (index.min(255) - 232) as u8; 3],
+        },
+    }
+}
+
+/// Writes the escape sequence that sets the foreground or background to a `code`.
+pub fn write_color<W: Write>(out: &mut W, depth: ColorDepth, code: u32, background: bool) -> io::Result<()> {
+    match depth {
+        ColorDepth::Auto | ColorDepth::Truecolor => {
+            write!(out, "\x1B[{};2;{};{};{}m", if background { 48 } else { 38 }, code >> 16, (code >> 8) & 0xFF, code & 0xFF)
+        },
+        ColorDepth::Ansi256 => write!(out, "\x1B[{};5;{}m", if background { 48 } else { 38 }, code),
+        ColorDepth::Ansi16 => {
+            let base = match (code >= 8, background) {
+                (false, false) => 30,
+                (false, true) => 40,
+                (true, false) => 90 - 8,
+                (true, true) => 100 - 8,
+            };
+            write!(out, "\x1B[{}m", base + code)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_colors_map_to_themselves() {
+        // The 256-color palette picks from the cube and the gray ramp only, the first 16
+        // colors are the ANSI ones the 16-color palette covers.
+        for code in 16..256 {
+            assert_eq!(self::code(ColorDepth::Ansi256, color(ColorDepth::Ansi256, code)), code, "color {}", code);
+        }
+        for code in 0..16 {
+            assert_eq!(self::code(ColorDepth::Ansi16, color(ColorDepth::Ansi16, code)), code, "color {}", code);
+        }
+    }
+
+    #[test]
+    fn grays_stay_on_the_gray_ramp() {
+        assert_eq!(code(ColorDepth::Ansi256, [128, 128, 128]), 244);
+        assert_eq!(code(ColorDepth::Ansi256, [130, 130, 130]), 244);
+        assert_eq!(code(ColorDepth::Ansi256, [137, 137, 137]), 245);
+    }
+
+    #[test]
+    fn truecolor_keeps_colors() {
+        let color = [12, 34, 56];
+        assert_eq!(nearest(ColorDepth::Truecolor, color), color);
+        assert_eq!(code(ColorDepth::Truecolor, color), 0x0C2238);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let written = |depth, code, background| {
+            let mut out = Vec::new();
+            write_color(&mut out, depth, code, background).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(written(ColorDepth::Truecolor, 0x0C2238, false), "\x1B[38;2;12;34;56m");
+        assert_eq!(written(ColorDepth::Ansi256, 244, true), "\x1B[48;5;244m");
+        assert_eq!(written(ColorDepth::Ansi16, 3, false), "\x1B[33m");
+        assert_eq!(written(ColorDepth::Ansi16, 12, true), "\x1B[104m");
+    }
+}
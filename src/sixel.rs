@@ -0,0 +1,241 @@
+use std::io::{self, Write};
+
+use crate::args::ColorDepth;
+use crate::palette;
+use crate::terminal::PixelFrame;
+
+// Pixels are drawn in the colors of the 256-color palette after the 16 ANSI ones, which
+// become color registers 0 to 239.
+const FIRST_COLOR: u32 = 16;
+const REGISTERS: usize = 240;
+
+// Repeats of a sixel are run-length encoded from this many on, below that the repeat
+// introducer takes more bytes than it saves.
+const MIN_RUN: usize = 4;
+
+/// Writes `frame` as a sixel image with its top left corner at the cursor, every pixel in
+/// the perceptually closest color of the 256-color palette.
+pub fn encode<W: Write>(out: &mut W, frame: &PixelFrame) -> io::Result<()> {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let registers = frame.rgb.chunks_exact(3)
+        .map(|pixel| (palette::code(ColorDepth::Ansi256, [pixel[0], pixel[1], pixel[2]]) - FIRST_COLOR) as u8)
+        .collect::<Vec<u8>>();
+
+    // Square pixels, a background that is left as it is, and the size of the image.
+    write!(out, "\x1BP0;1;0q\"1;1;{};{}", width, height)?;
+    let mut used = [false; REGISTERS];
+    for &register in &registers {
+        used[register as usize] = true;
+    }
+    for register in (0..REGISTERS).filter(|&register| used[register]) {
+        // Colors are defined in percent.
+        let [r, g, b] = palette::color(ColorDepth::Ansi256, register as u32 + FIRST_COLOR).map(|value| (value as u32 * 100 + 127) / 255);
+        write!(out, "#{};2;{};{};{}", register, r, g, b)?;
+    }
+
+    // Every band of six rows is drawn one color after another, going back to the start
+    // of the band in between. A sixel holds the pixels of a column in that color as bits.
+    let mut sixels = vec![0u8; REGISTERS * width];
+    for top in (0..height).step_by(6) {
+        let mut in_band = [false; REGISTERS];
+        for row in 0..6.min(height - top) {
+            for x in 0..width {
+                let register = registers[(top + row) * width + x] as usize;
+                in_band[register] = true;
+                sixels[register * width + x] |= 1 << row;
+            }
+        }
+
+        let mut first = true;
+        for register in (0..REGISTERS).filter(|&register| in_band[register]) {
+            if !first {
+                out.write_all(b"$")?;
+            }
+            first = false;
+
+            write!(out, "#{}", register)?;
+            let line = &mut sixels[register * width..(register + 1) * width];
+            write_runs(out, line)?;
+            line.fill(0);
+        }
+        out.write_all(b"-")?;
+    }
+
+    out.write_all(b"\x1B\\")
+}
+
+// Empty sixels at the end of a line are left out, the next color starts over anyway.
+fn write_runs<W: Write>(out: &mut W, line: &[u8]) -> io::Result<()> {
+    let line = &line[..line.iter().rposition(|&bits| bits != 0).map_or(0, |last| last + 1)];
+    let mut start = 0;
+    while start < line.len() {
+        let bits = line[start];
+        let count = line[start..].iter().position(|&other| other != bits).unwrap_or(line.len() - start);
+        let sixel = 0x3F + bits;
+        if count >= MIN_RUN {
+            write!(out, "!{}{}", count, sixel as char)?;
+        }
+        else {
+            for _ in 0..count {
+                out.write_all(&[sixel])?;
+            }
+        }
+        start += count;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// The parts of a decoded sixel image: its size from the raster attributes, the color
+    /// registers in percent, and the register every pixel was drawn with.
+    struct Decoded {
+        width: usize,
+        height: usize,
+        registers: HashMap<usize, [u32; 3]>,
+        pixels: Vec<Option<usize>>,
+    }
+
+    fn number(bytes: &[u8], index: &mut usize) -> usize {
+        let start = *index;
+        while bytes[*index].is_ascii_digit() {
+            *index += 1;
+        }
+        std::str::from_utf8(&bytes[start..*index]).unwrap().parse().unwrap()
+    }
+
+    fn numbers(bytes: &[u8], index: &mut usize) -> Vec<usize> {
+        let mut numbers = vec![number(bytes, index)];
+        while bytes[*index] == b';' {
+            *index += 1;
+            numbers.push(number(bytes, index));
+        }
+        numbers
+    }
+
+    fn decode(bytes: &[u8]) -> Decoded {
+        assert!(bytes.starts_with(b"\x1BP0;1;0q\""), "no sixel introducer with raster attributes");
+        assert!(bytes.ends_with(b"\x1B\\"), "no string terminator");
+        let mut index = 9;
+        let raster = numbers(bytes, &mut index);
+        assert_eq!(raster[..2], [1, 1], "pixels are not square");
+        let (width, height) = (raster[2], raster[3]);
+
+        let mut decoded = Decoded { width, height, registers: HashMap::new(), pixels: vec![None; width * height] };
+        let (mut x, mut band, mut register) = (0, 0, None);
+        let end = bytes.len() - 2;
+        while index < end {
+            let mut count = 1;
+            match bytes[index] {
+                b'#' => {
+                    index += 1;
+                    let parameters = numbers(bytes, &mut index);
+                    if parameters.len() == 5 {
+                        assert_eq!(parameters[1], 2, "color is not RGB");
+                        decoded.registers.insert(parameters[0], [parameters[2] as u32, parameters[3] as u32, parameters[4] as u32]);
+                    }
+                    else {
+                        assert!(decoded.registers.contains_key(&parameters[0]), "register {} selected before it is defined", parameters[0]);
+                        register = Some(parameters[0]);
+                    }
+                    continue;
+                },
+                b'$' => {
+                    x = 0;
+                    index += 1;
+                    continue;
+                },
+                b'-' => {
+                    x = 0;
+                    band += 1;
+                    index += 1;
+                    continue;
+                },
+                b'!' => {
+                    index += 1;
+                    count = number(bytes, &mut index);
+                },
+                _ => {},
+            }
+
+            let sixel = bytes[index];
+            assert!((0x3F..=0x7E).contains(&sixel), "unexpected byte {}", sixel);
+            for _ in 0..count {
+                for row in 0..6 {
+                    if (sixel - 0x3F) >> row & 1 == 1 {
+                        let y = band * 6 + row;
+                        assert!(x < width && y < height, "pixel {}, {} outside the image", x, y);
+                        assert!(decoded.pixels[y * width + x].is_none(), "pixel {}, {} drawn twice", x, y);
+                        decoded.pixels[y * width + x] = Some(register.expect("no register selected"));
+                    }
+                }
+                x += 1;
+            }
+            index += 1;
+        }
+
+        decoded
+    }
+
+    fn percent(color: [u8; 3]) -> [u32; 3] {
+        color.map(|value| (value as u32 * 100 + 127) / 255)
+    }
+
+    fn frame(width: u32, height: u32, rgb: Vec<u8>) -> PixelFrame {
+        PixelFrame { width, height, columns: 1, rows: 1, rgb }
+    }
+
+    #[test]
+    fn decodes_to_the_nearest_palette_colors() {
+        // An odd width, and a height that ends in a partial band.
+        let (width, height) = (13usize, 17usize);
+        let rgb = (0..width * height)
+            .flat_map(|index| {
+                let (x, y) = (index % width, index / width);
+                [(x * 19) as u8, (y * 15) as u8, ((x + y) * 9) as u8]
+            })
+            .collect::<Vec<u8>>();
+
+        let mut out = Vec::new();
+        encode(&mut out, &frame(width as u32, height as u32, rgb.clone())).unwrap();
+        let decoded = decode(&out);
+
+        assert_eq!((decoded.width, decoded.height), (width, height));
+        for (index, pixel) in rgb.chunks_exact(3).enumerate() {
+            let register = decoded.pixels[index].unwrap_or_else(|| panic!("pixel {} not drawn", index));
+            let code = palette::code(ColorDepth::Ansi256, [pixel[0], pixel[1], pixel[2]]);
+            assert_eq!(register, (code - FIRST_COLOR) as usize);
+            assert_eq!(decoded.registers[&register], percent(palette::color(ColorDepth::Ansi256, code)));
+        }
+    }
+
+    #[test]
+    fn palette_colors_round_trip() {
+        // Every color of the palette once, 16 per row.
+        let colors = (FIRST_COLOR..FIRST_COLOR + REGISTERS as u32).map(|code| palette::color(ColorDepth::Ansi256, code)).collect::<Vec<[u8; 3]>>();
+        let (width, height) = (16, REGISTERS / 16);
+        let mut out = Vec::new();
+        encode(&mut out, &frame(width as u32, height as u32, colors.concat())).unwrap();
+        let decoded = decode(&out);
+
+        assert!(decoded.registers.keys().all(|&register| register < REGISTERS));
+        for (index, color) in colors.iter().enumerate() {
+            assert_eq!(decoded.registers[&decoded.pixels[index].unwrap()], percent(*color), "color {}", index);
+        }
+    }
+
+    #[test]
+    fn runs_are_repeated() {
+        let width = 20;
+        let mut out = Vec::new();
+        encode(&mut out, &frame(width, 6, [255, 0, 0].repeat(width as usize * 6))).unwrap();
+        assert!(out.windows(4).any(|window| window == b"!20~"));
+        assert!(decode(&out).pixels.iter().all(Option::is_some));
+    }
+}
@@ -1,69 +1,639 @@
-use std::io::{stdout, Write};
+use std::env;
+use std::io::{self, stdout, Stdout, Write};
+use std::mem;
+use std::panic;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
-use crossbeam::channel::Receiver;
+use crossbeam::channel::{select, Receiver};
+use termion::raw::{IntoRawMode, RawTerminal};
+use crate::args::{ColorDepth, ColorMode, Output};
+use crate::config::Settings;
 use crate::event_loop::LoopEvent;
 use crate::controller::Controller;
+use crate::iterm;
+use crate::kitty;
+use crate::palette;
+use crate::sixel;
 
+/// A frame as a grid of cells, `width` cells per row and rows one after another.
 pub struct StringInfo {
+    // Bytes every glyph in `string` takes, shorter ones are padded with zeros.
     pub char_len: u32,
+    pub width: u32,
+    // UTF-8 glyph of every cell.
     pub string: Vec<u8>,
+    // Foreground color of every cell as R, G, B, empty to keep the terminal's own.
     pub rgb: Vec<u8>,
+    // Background color of every cell in the same layout as `rgb`, empty to keep the
+    // terminal's own background.
+    pub background: Vec<u8>,
+}
+
+/// A frame as RGB pixels, `width` x `height` of them row by row, covering `columns` x
+/// `rows` terminal cells.
+pub struct PixelFrame {
+    pub width: u32,
+    pub height: u32,
+    pub columns: u16,
+    pub rows: u16,
+    pub rgb: Vec<u8>,
+}
+
+/// What the media controller sends the terminal, depending on --output.
+pub enum Frame {
+    Text(StringInfo),
+    Pixels(PixelFrame),
+}
+
+impl Frame {
+    /// Columns and rows of terminal cells the frame takes.
+    pub fn cells(&self) -> (u16, u16) {
+        match self {
+            Frame::Text(string) => (string.width as u16, (string.string.len() / (string.width * string.char_len).max(1) as usize) as u16),
+            Frame::Pixels(pixels) => (pixels.columns, pixels.rows),
+        }
+    }
 }
 
 pub struct TerminalController<'a> {
-    media_receiver: &'a Receiver<StringInfo>,
+    media_receiver: &'a Receiver<Frame>,
     event_loop_receiver: &'a Receiver<LoopEvent>,
+    synchronized: bool,
+    color_mode: ColorMode,
+    color_depth: ColorDepth,
+    output: Output,
 }
 
 impl<'a> TerminalController<'a> { 
-    pub fn new(media_receiver: &'a Receiver<StringInfo>, event_loop_receiver: &'a Receiver<LoopEvent>) -> Self {
+    pub fn new(settings: &Settings, media_receiver: &'a Receiver<Frame>, event_loop_receiver: &'a Receiver<LoopEvent>, synchronized: bool) -> Self {
         Self { 
             media_receiver,
             event_loop_receiver,
+            synchronized,
+            color_mode: settings.color_mode,
+            color_depth: settings.color_depth,
+            output: settings.output,
+        }
+    }
+}
+
+// Raw mode of the terminal while playing, kept where `restore` can reach it from any
+// thread and from the panic hook.
+static RAW_TERMINAL: Mutex<Option<RawTerminal<Stdout>>> = Mutex::new(None);
+
+// Whether a kitty graphics image was drawn, which stays in the terminal's memory until
+// it is deleted.
+static KITTY_IMAGE: AtomicBool = AtomicBool::new(false);
+
+// Begin and end of a DEC mode 2026 synchronized update, during which the terminal holds
+// back drawing so that a frame never shows half written.
+const BEGIN_SYNCHRONIZED: &[u8] = b"\x1B[?2026h";
+const END_SYNCHRONIZED: &[u8] = b"\x1B[?2026l";
+
+/// Switches the terminal to raw mode and the alternate screen without a cursor, and
+/// tells whether it supports synchronized updates. Everything is undone by `restore`,
+/// which also runs when a thread panics.
+pub fn enter() -> io::Result<bool> {
+    let mut raw_terminal = stdout().into_raw_mode()?;
+    write!(raw_terminal, "{}{}", termion::screen::ToAlternateScreen, termion::cursor::Hide)?;
+    raw_terminal.flush()?;
+    let synchronized = supports_synchronized_output(&mut raw_terminal);
+    *RAW_TERMINAL.lock().unwrap_or_else(|error| error.into_inner()) = Some(raw_terminal);
+
+    // The other threads would wait for a panicked one forever, so the process ends too.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        default_hook(info);
+        process::exit(101);
+    }));
+
+    Ok(synchronized)
+}
+
+/// Gives back the main screen with its cursor, colors and cooked mode. Does nothing
+/// when the terminal was never entered or is already restored.
+pub fn restore() {
+    let raw_terminal = RAW_TERMINAL.lock().unwrap_or_else(|error| error.into_inner()).take();
+    if let Some(mut raw_terminal) = raw_terminal {
+        if KITTY_IMAGE.load(Ordering::Relaxed) {
+            let _ = kitty::delete(&mut raw_terminal);
+        }
+        let _ = write!(raw_terminal, "{}{}{}", termion::style::Reset, termion::cursor::Show, termion::screen::ToMainScreen);
+        let _ = raw_terminal.flush();
+    }
+}
+
+/// Sends `request` to the terminal and reads its answer from stdin until `done` says it
+/// is complete. Has to run in raw mode before anything else reads stdin; the answer ends
+/// early when the terminal stays silent for 100 ms.
+fn query<W: Write>(out: &mut W, request: &[u8], done: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut response = Vec::new();
+    if out.write_all(request).and_then(|_| out.flush()).is_err() {
+        return response;
+    }
+
+    let mut byte = 0u8;
+    let mut poll = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+    while !done(&response) && response.len() < 256 {
+        if unsafe { libc::poll(&mut poll, 1, 100) } <= 0 {
+            break;
+        }
+        if unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) } != 1 {
+            break;
         }
+        response.push(byte);
+    }
+
+    response
+}
+
+/// Asks the terminal with DECRQM whether it knows mode 2026. Terminals that do not answer
+/// are taken not to support it.
+fn supports_synchronized_output<W: Write>(out: &mut W) -> bool {
+    let response = query(out, b"\x1B[?2026$p", |response| response.ends_with(b"$y"));
+
+    // The answer is CSI ? 2026 ; Ps $ y, where 0 means unknown and 4 permanently off.
+    [b"2026;1$y", b"2026;2$y", b"2026;3$y"].iter().any(|answer| response.ends_with(*answer))
+}
+
+/// Picks the output for --output auto: kitty graphics when the terminal answers the
+/// protocol's query, iTerm2 images when iTerm2 says it is the terminal, and text
+/// otherwise or when stdout is not a terminal.
+pub fn detect_output() -> Output {
+    let Ok(mut raw_terminal) = stdout().into_raw_mode() else {
+        return Output::Text;
+    };
+
+    // Every terminal answers primary device attributes, after anything asked before, so
+    // that answer ends the wait without a timeout on terminals that ignore the query.
+    let request = [kitty::QUERY, b"\x1B[c".as_slice()].concat();
+    let response = query(&mut raw_terminal, &request, |response| {
+        response.ends_with(b"c") && response.windows(3).any(|window| window == b"\x1B[?")
+    });
+
+    if response.windows(kitty::QUERY_OK.len()).any(|window| window == kitty::QUERY_OK) {
+        Output::Kitty
+    }
+    else if env::var("TERM_PROGRAM").is_ok_and(|name| name == "iTerm.app") || env::var("LC_TERMINAL").is_ok_and(|name| name == "iTerm2") {
+        Output::Iterm
+    }
+    else {
+        Output::Text
+    }
+}
+
+/// Writes a frame to `out` with its top left corner at the 1-based column and row of
+/// `origin`, one row of the glyph grid per terminal line, with colors of `color_depth`.
+pub fn encode<W: Write>(out: &mut W, string: &StringInfo, origin: (u16, u16), color_depth: ColorDepth) -> io::Result<()> {
+    let columns = string.width as usize;
+    let row_len = (columns * string.char_len as usize).max(1);
+    let mut foreground = None;
+    let mut background = None;
+    for (row, line) in string.string.chunks(row_len).enumerate() {
+        write!(out, "{}", termion::cursor::Goto(origin.0, origin.1 + row as u16))?;
+        if string.rgb.is_empty() && string.background.is_empty() {
+            out.write_all(line)?;
+            continue;
+        }
+
+        for column in 0..columns {
+            write_cell(out, string, row * columns + column, color_depth, &mut foreground, &mut background)?;
+        }
+    }
+
+    finish(out, foreground.is_some() || background.is_some())
+}
+
+// Unchanged cells between two changed ones are rewritten instead of jumped over when
+// there are at most this many, a cursor move costs about as many bytes.
+const MAX_GAP: usize = 4;
+
+// Share of changed cells above which a frame is drawn completely instead of cell by cell.
+const FULL_REDRAW_RATIO: f64 = 0.5;
+
+fn same_layout(a: &StringInfo, b: &StringInfo) -> bool {
+    a.width == b.width && a.char_len == b.char_len && a.string.len() == b.string.len()
+        && a.rgb.len() == b.rgb.len() && a.background.len() == b.background.len()
+}
+
+fn cell_changed(string: &StringInfo, previous: &StringInfo, index: usize) -> bool {
+    let char_len = string.char_len as usize;
+    let glyph = index * char_len..(index + 1) * char_len;
+    let color = index * 3..index * 3 + 3;
+    string.string[glyph.clone()] != previous.string[glyph]
+        || (!string.rgb.is_empty() && string.rgb[color.clone()] != previous.rgb[color.clone()])
+        || (!string.background.is_empty() && string.background[color.clone()] != previous.background[color])
+}
+
+/// Writes the glyph of a cell, preceded by the colors it needs when they differ from
+/// the ones last written, which start out unknown. Colors are kept as `palette::code`s,
+/// so that neighbours mapped to the same palette color need no escape sequence.
+fn write_cell<W: Write>(out: &mut W, string: &StringInfo, index: usize, color_depth: ColorDepth, foreground: &mut Option<u32>, background: &mut Option<u32>) -> io::Result<()> {
+    if !string.rgb.is_empty() {
+        let code = palette::code(color_depth, [string.rgb[index * 3], string.rgb[index * 3 + 1], string.rgb[index * 3 + 2]]);
+        if *foreground != Some(code) {
+            palette::write_color(out, color_depth, code, false)?;
+            *foreground = Some(code);
+        }
+    }
+
+    if !string.background.is_empty() {
+        let code = palette::code(color_depth, [string.background[index * 3], string.background[index * 3 + 1], string.background[index * 3 + 2]]);
+        if *background != Some(code) {
+            palette::write_color(out, color_depth, code, true)?;
+            *background = Some(code);
+        }
+    }
+
+    let char_len = string.char_len as usize;
+    out.write_all(&string.string[index * char_len..(index + 1) * char_len])
+}
+
+/// Writes only the cells of `string` that differ from `previous`, the frame with the
+/// same layout that is on screen at `origin`, moving the cursor over unchanged ones.
+pub fn encode_changes<W: Write>(out: &mut W, string: &StringInfo, previous: &StringInfo, origin: (u16, u16), color_depth: ColorDepth) -> io::Result<()> {
+    let columns = string.width as usize;
+    let rows = string.string.len() / (columns * string.char_len as usize).max(1);
+    let mut foreground = None;
+    let mut background = None;
+    for row in 0..rows {
+        let mut column = 0;
+        while column < columns {
+            if !cell_changed(string, previous, row * columns + column) {
+                column += 1;
+                continue;
+            }
+
+            let start = column;
+            let mut end = column + 1;
+            let mut next = end;
+            while next < columns && next - end <= MAX_GAP {
+                if cell_changed(string, previous, row * columns + next) {
+                    end = next + 1;
+                }
+                next += 1;
+            }
+
+            write!(out, "{}", termion::cursor::Goto(origin.0 + start as u16, origin.1 + row as u16))?;
+            for cell in start..end {
+                write_cell(out, string, row * columns + cell, color_depth, &mut foreground, &mut background)?;
+            }
+            column = end;
+        }
+    }
+
+    finish(out, foreground.is_some() || background.is_some())
+}
+
+// Colors are reset after a frame so that nothing drawn later, like clearing the screen,
+// inherits them.
+fn finish<W: Write>(out: &mut W, colored: bool) -> io::Result<()> {
+    if colored {
+        write!(out, "{}", termion::style::Reset)?;
+    }
+    write!(out, "{}", termion::cursor::Goto(1, 1))
+}
+
+/// Draws `string` at `origin` over `previous`, the frame that is on screen there if
+/// any. Only changed cells are written, unless most of them changed or the layout is
+/// different and the whole frame is written anyway.
+pub fn encode_update<W: Write>(out: &mut W, string: &StringInfo, previous: Option<&StringInfo>, origin: (u16, u16), color_depth: ColorDepth) -> io::Result<()> {
+    match previous {
+        Some(previous) if same_layout(string, previous) => {
+            let cells = string.string.len() / (string.char_len as usize).max(1);
+            let changed = (0..cells).filter(|&index| cell_changed(string, previous, index)).count();
+            if changed as f64 > cells as f64 * FULL_REDRAW_RATIO {
+                encode(out, string, origin, color_depth)
+            }
+            else {
+                encode_changes(out, string, previous, origin, color_depth)
+            }
+        },
+        _ => encode(out, string, origin, color_depth),
+    }
+}
+
+// How bright the background behind a glyph is in the background color mode, relative to
+// the color of its cell.
+const BACKGROUND_SHADE: u32 = 40;
+
+/// Moves the foreground colors of a frame to where `color_mode` shows them. Frames that
+/// come with their own background, like the block render modes, are left as they are.
+fn paint(mut string: StringInfo, color_mode: ColorMode) -> StringInfo {
+    if string.rgb.is_empty() || !string.background.is_empty() {
+        return string;
+    }
+
+    match color_mode {
+        ColorMode::Color | ColorMode::Grayscale => {},
+        ColorMode::Block => {
+            let cells = string.string.len() / (string.char_len as usize).max(1);
+            string.string = vec![b' '; cells];
+            string.char_len = 1;
+            string.background = mem::take(&mut string.rgb);
+        },
+        ColorMode::Background => {
+            string.background = string.rgb.iter().map(|&value| (value as u32 * BACKGROUND_SHADE / 100) as u8).collect();
+        },
     }
+
+    string
+}
+
+/// Where a frame of `columns` x `rows` cells starts to be centered in a terminal of
+/// `terminal_size` cells.
+fn centered_origin(columns: u16, rows: u16, terminal_size: (u16, u16)) -> (u16, u16) {
+    (terminal_size.0.saturating_sub(columns) / 2 + 1, terminal_size.1.saturating_sub(rows) / 2 + 1)
 }
 
 impl<'a> Controller for TerminalController<'a> {
     fn run(&mut self) {
-        print!("{}", termion::cursor::Hide);
-        let mut stdout = stdout();
+        let stdout = stdout();
+        let mut buffer = Vec::new();
+        let mut terminal_size = termion::terminal_size().unwrap_or((80, 24));
+        let mut last_layout = None;
+        let mut previous: Option<StringInfo> = None;
         loop {
-            if !self.event_loop_receiver.is_empty() {
-                let event = self.event_loop_receiver.recv().unwrap();
-                if let LoopEvent::Shutdown = event {
-                    break;
+            select! {
+                recv(self.event_loop_receiver) -> event => match event {
+                    Ok(LoopEvent::Shutdown) | Err(_) => break,
+                    // The terminal may have reflowed what was on it, so the next frame is
+                    // drawn on a cleared screen.
+                    Ok(LoopEvent::Resize) => {
+                        terminal_size = termion::terminal_size().unwrap_or(terminal_size);
+                        last_layout = None;
+                        previous = None;
+                    },
+                    _ => {},
+                },
+                recv(self.media_receiver) -> frame => {
+                    let Ok(mut frame) = frame else {
+                        break;
+                    };
+                    // Frames that queued up while the last one was written are out of date,
+                    // only the newest is drawn. Cells are still compared with what is on
+                    // screen, so skipping some is safe.
+                    while let Ok(newer) = self.media_receiver.try_recv() {
+                        frame = newer;
+                    }
+                    let (columns, rows) = frame.cells();
+                    let origin = centered_origin(columns, rows, terminal_size);
+
+                    // The whole frame goes out in one write, inside a synchronized update
+                    // where supported, so it is never shown half drawn.
+                    buffer.clear();
+                    if self.synchronized {
+                        buffer.extend_from_slice(BEGIN_SYNCHRONIZED);
+                    }
+                    // Whatever was drawn around a frame of another size or place has to go.
+                    if last_layout != Some((columns, rows, origin)) {
+                        write!(buffer, "{}", termion::clear::All).unwrap();
+                        last_layout = Some((columns, rows, origin));
+                        previous = None;
+                    }
+                    match frame {
+                        Frame::Text(string) => {
+                            let string = paint(string, self.color_mode);
+                            encode_update(&mut buffer, &string, previous.as_ref(), origin, self.color_depth).unwrap();
+                            previous = Some(string);
+                        },
+                        Frame::Pixels(pixels) => {
+                            write!(buffer, "{}", termion::cursor::Goto(origin.0, origin.1)).unwrap();
+                            match self.output {
+                                Output::Kitty => {
+                                    kitty::encode(&mut buffer, &pixels).unwrap();
+                                    KITTY_IMAGE.store(true, Ordering::Relaxed);
+                                },
+                                Output::Iterm => iterm::encode(&mut buffer, &pixels).unwrap(),
+                                _ => sixel::encode(&mut buffer, &pixels).unwrap(),
+                            }
+                            previous = None;
+                        },
+                    }
+                    if self.synchronized {
+                        buffer.extend_from_slice(END_SYNCHRONIZED);
+                    }
+
+                    let mut locked = stdout.lock();
+                    locked.write_all(&buffer).unwrap();
+                    locked.flush().unwrap();
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::ascii;
+
+    // What a terminal shows in a cell: the glyph and its foreground and background color.
+    type Cell = (char, Option<[u8; 3]>, Option<[u8; 3]>);
+
+    /// A terminal that understands what the encoder writes: cursor moves, truecolor SGR
+    /// codes, resets and UTF-8 glyphs. Cells keep whatever was last drawn into them.
+    #[derive(Default)]
+    struct Screen {
+        cells: HashMap<(u16, u16), Cell>,
+        cursor: (u16, u16),
+        foreground: Option<[u8; 3]>,
+        background: Option<[u8; 3]>,
+    }
+
+    impl Screen {
+        fn apply(&mut self, bytes: &[u8]) {
+            let mut index = 0;
+            while index < bytes.len() {
+                if bytes[index] == 0x1B {
+                    assert_eq!(bytes[index + 1], b'[', "unexpected escape sequence");
+                    let end = index + 2 + bytes[index + 2..].iter().position(|byte| byte.is_ascii_alphabetic()).unwrap();
+                    let parameters = std::str::from_utf8(&bytes[index + 2..end]).unwrap()
+                        .split(';').filter(|parameter| !parameter.is_empty())
+                        .map(|parameter| parameter.parse::<u8>().unwrap()).collect::<Vec<u8>>();
+                    match bytes[end] {
+                        b'H' => self.cursor = (parameters[1] as u16, parameters[0] as u16),
+                        b'm' if parameters.is_empty() || parameters == [0] => (self.foreground, self.background) = (None, None),
+                        b'm' if parameters[..2] == [38, 2] => self.foreground = Some([parameters[2], parameters[3], parameters[4]]),
+                        b'm' if parameters[..2] == [48, 2] => self.background = Some([parameters[2], parameters[3], parameters[4]]),
+                        other => panic!("unexpected escape sequence ending in {}", other as char),
+                    }
+                    index = end + 1;
+                }
+                // Glyphs are padded with zero bytes, which terminals skip.
+                else if bytes[index] == 0 {
+                    index += 1;
+                }
+                else {
+                    let len = match bytes[index] {
+                        0x00..=0x7F => 1,
+                        0xC0..=0xDF => 2,
+                        0xE0..=0xEF => 3,
+                        _ => 4,
+                    };
+                    let glyph = std::str::from_utf8(&bytes[index..index + len]).unwrap().chars().next().unwrap();
+                    self.cells.insert(self.cursor, (glyph, self.foreground, self.background));
+                    self.cursor.0 += 1;
+                    index += len;
                 }
             }
+        }
+    }
 
-            let string = self.media_receiver.recv().unwrap();
-            let mut locked = stdout.lock();
+    fn frame(width: u32, glyphs: &str, rgb: Vec<u8>, background: Vec<u8>) -> StringInfo {
+        let (string, char_len) = ascii::encode_chars(glyphs);
+        StringInfo { char_len, width, string, rgb, background }
+    }
 
-            write!(&stdout, "{}", termion::cursor::Goto(1, 1)).unwrap();
-            if string.rgb.is_empty() {
-                locked.write_all(&string.string).unwrap();
-                locked.flush().unwrap();
-                continue;
+    fn encoded(string: &StringInfo, origin: (u16, u16)) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode(&mut out, string, origin, ColorDepth::Truecolor).unwrap();
+        out
+    }
+
+    fn screen_of(string: &StringInfo, origin: (u16, u16)) -> Screen {
+        let mut screen = Screen::default();
+        screen.apply(&encoded(string, origin));
+        screen
+    }
+
+    #[test]
+    fn last_run_of_a_color_is_written() {
+        let string = frame(4, "abcdefgh", [7, 8, 9].repeat(8), Vec::new());
+        let screen = screen_of(&string, (1, 1));
+        for (index, glyph) in "abcdefgh".chars().enumerate() {
+            assert_eq!(screen.cells[&(1 + index as u16 % 4, 1 + index as u16 / 4)], (glyph, Some([7, 8, 9]), None));
+        }
+        assert_eq!(screen.cells.len(), 8);
+    }
+
+    #[test]
+    fn colors_are_reset_at_the_end() {
+        let colored = encoded(&frame(2, "ab", vec![1, 2, 3, 4, 5, 6], Vec::new()), (1, 1));
+        assert!(colored.ends_with(b"\x1B[m\x1B[1;1H"));
+
+        let background = encoded(&frame(2, "ab", Vec::new(), vec![1, 2, 3, 4, 5, 6]), (1, 1));
+        assert!(background.ends_with(b"\x1B[m\x1B[1;1H"));
+
+        // Without colors there is nothing to reset.
+        let plain = encoded(&frame(2, "ab", Vec::new(), Vec::new()), (1, 1));
+        assert!(!plain.windows(3).any(|window| window == b"\x1B[m"));
+        assert!(plain.ends_with(b"\x1B[1;1H"));
+    }
+
+    #[test]
+    fn colors_are_written_as_rgb() {
+        let out = encoded(&frame(1, "a", vec![10, 20, 30], vec![40, 50, 60]), (1, 1));
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\x1B[38;2;10;20;30m"));
+        assert!(out.contains("\x1B[48;2;40;50;60m"));
+    }
+
+    #[test]
+    fn every_row_starts_with_a_cursor_move() {
+        for rgb in [Vec::new(), [1, 2, 3].repeat(6)] {
+            let string = frame(2, "abcdef", rgb, Vec::new());
+            let out = String::from_utf8(encoded(&string, (5, 7))).unwrap();
+            for row in 7..10 {
+                assert!(out.contains(&format!("\x1B[{};5H", row)), "no cursor move to row {}", row);
             }
-            
-            let mut current_color: (u8, u8, u8) = (0, 0, 0); 
-            let mut last_color_change_index = 0;
-            let mut rgb_index = 0;
-            for index in (0..string.string.len()).step_by(string.char_len as usize) {
-                if current_color != (string.rgb[rgb_index], string.rgb[rgb_index + 1], string.rgb[rgb_index + 2]) {
-                    locked.write_all(&string.string[last_color_change_index..index]).unwrap();
-                    last_color_change_index = index;
-
-                    write!(&mut locked, "{}", format!("\x1B[38;2;{};{};{}m", string.rgb[rgb_index + 2], string.rgb[rgb_index + 1], string.rgb[rgb_index]).as_str()).unwrap();     
-                    current_color = (string.rgb[rgb_index], string.rgb[rgb_index + 1], string.rgb[rgb_index + 2]);
-                } 
-
-                rgb_index += 3;
+
+            let screen = screen_of(&string, (5, 7));
+            for (index, glyph) in "abcdef".chars().enumerate() {
+                assert_eq!(screen.cells[&(5 + index as u16 % 2, 7 + index as u16 / 2)].0, glyph);
             }
-            write!(&mut locked, "{}", termion::cursor::Goto(1, 1));
-            stdout.flush().unwrap();
         }
+    }
+
+    /// Draws `previous` and then `string` over it with `encode_update`, checks that this
+    /// shows the same as drawing `string` alone, and returns the update.
+    fn check_update(previous: &StringInfo, string: &StringInfo) -> Vec<u8> {
+        let origin = (3, 2);
+        let mut screen = screen_of(previous, origin);
+        let mut update = Vec::new();
+        encode_update(&mut update, string, Some(previous), origin, ColorDepth::Truecolor).unwrap();
+        screen.apply(&update);
+
+        assert_eq!(screen.cells, screen_of(string, origin).cells);
+        assert_eq!((screen.foreground, screen.background), (None, None));
+        update
+    }
+
+    fn colors(cells: usize, seed: u8) -> Vec<u8> {
+        (0..cells * 3).map(|index| (index as u8).wrapping_mul(37).wrapping_add(seed)).collect()
+    }
+
+    #[test]
+    fn update_with_few_changes_writes_only_them() {
+        let glyphs = "abcdefghijklmnopqrstuvwxyz0123456789";
+        let previous = frame(12, glyphs, colors(36, 0), Vec::new());
+
+        // A change, a gap of MAX_GAP unchanged cells that is rewritten, another change,
+        // and then a gap of MAX_GAP + 1 cells that is jumped over.
+        let changed = [0, MAX_GAP + 1, 2 * MAX_GAP + 3, 30];
+        let mut string = frame(12, glyphs, colors(36, 0), Vec::new());
+        for &index in &changed {
+            string.string[index] = b'#';
+            string.rgb[index * 3] ^= 0xFF;
+        }
+
+        let update = check_update(&previous, &string);
+        assert!(update.len() < encoded(&string, (3, 2)).len());
+
+        // Cursor moves to the first change of a run and past the second gap only.
+        let update = String::from_utf8(update).unwrap();
+        assert!(update.contains("\x1B[2;3H"));
+        assert!(!update.contains(&format!("\x1B[2;{}H", 3 + MAX_GAP + 1)));
+        assert!(update.contains(&format!("\x1B[2;{}H", 3 + 2 * MAX_GAP + 3)));
+        assert!(update.contains(&format!("\x1B[4;{}H", 3 + 30 % 12)));
+    }
+
+    #[test]
+    fn update_of_a_frame_without_colors() {
+        let previous = frame(6, "abcdefghijkl", Vec::new(), Vec::new());
+        let string = frame(6, "abcdefghXjkl", Vec::new(), Vec::new());
+        check_update(&previous, &string);
+    }
+
+    #[test]
+    fn update_with_background_colors() {
+        let previous = frame(4, "▀▀▀▀▀▀▀▀", colors(8, 0), colors(8, 1));
+        let mut string = frame(4, "▀▀▀▀▀▀▀▀", colors(8, 0), colors(8, 1));
+        string.background[3 * 3 + 1] ^= 0xFF;
+        check_update(&previous, &string);
+    }
+
+    #[test]
+    fn update_with_most_cells_changed_redraws_everything() {
+        let previous = frame(4, "abcdefgh", colors(8, 0), Vec::new());
+        let cells = 8;
+        let changed = (cells as f64 * FULL_REDRAW_RATIO) as usize + 1;
+        let mut string = frame(4, "abcdefgh", colors(8, 0), Vec::new());
+        for index in 0..changed {
+            string.string[index] = b'#';
+        }
+
+        assert_eq!(check_update(&previous, &string), encoded(&string, (3, 2)));
+
+        // At the ratio itself only the changes are written.
+        let mut string = frame(4, "abcdefgh", colors(8, 0), Vec::new());
+        for index in 0..changed - 1 {
+            string.string[index] = b'#';
+        }
+        assert_ne!(check_update(&previous, &string), encoded(&string, (3, 2)));
+    }
 
-        print!("{}", termion::cursor::Show);
+    #[test]
+    fn update_of_another_layout_redraws_everything() {
+        let previous = frame(4, "abcdefgh", colors(8, 0), Vec::new());
+        let string = frame(2, "abcdefgh", colors(8, 0), Vec::new());
+        let mut update = Vec::new();
+        encode_update(&mut update, &string, Some(&previous), (1, 1), ColorDepth::Truecolor).unwrap();
+        assert_eq!(update, encoded(&string, (1, 1)));
     }
 }